@@ -22,7 +22,7 @@ logging.basicConfig(
 logging.info("started")
 
 try:
-    from sentence_parser import STYPE_SEC, STYPE_AUX, PRIM_GL, SEC_GL, AUX_GL
+    from sentence_parser import STYPE_SEC, STYPE_AUX, PRIM_GL, SEC_GL, AUX_GL, prim_to_bpe, unmask_numbers
     from network import into_one_hot, generate_batch, load_from_save
 
     enc, sec_dec, aux_dec, *_ = load_from_save()
@@ -66,7 +66,7 @@ class WebInterface:
 
             start = time.time()
 
-            bpe = PRIM_GL.str_to_bpe(data["input"])
+            bpe, numbers = prim_to_bpe(data["input"])
             xs = torch.LongTensor([bpe])
 
             confidence_boost = data.get("confidence_boost", 1)
@@ -99,7 +99,7 @@ class WebInterface:
             confidences = torch.gather(out, 1, hard_out.view(-1, 1))
             confidence = confidences.prod().item()
 
-            hy_words = [SEC_GL.bpe_to_str([word]) for word in hard_out]
+            hy_words = [unmask_numbers(SEC_GL.bpe_to_str([word]), numbers) for word in hard_out]
 
             if did_cuttof:
                 out = "".join(hy_words)
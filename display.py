@@ -3,7 +3,7 @@ import numpy as np
 
 import matplotlib.pyplot as plt
 
-from sentence_parser import STYPE_SEC, STYPE_AUX, PRIM_GL, SEC_GL, AUX_GL
+from sentence_parser import STYPE_SEC, STYPE_AUX, PRIM_GL, SEC_GL, AUX_GL, prim_to_bpe, unmask_numbers
 from network import into_one_hot, generate_batch, load_from_save
 
 enc, sec_dec, aux_dec, *_ = load_from_save()
@@ -15,7 +15,7 @@ for name, gl, dec, stype in [sec_info, aux_info]:
     xs, ys = generate_batch(5, stype, max_length=-1)
 
     extra = input("Your own phrase> ")
-    bpe = PRIM_GL.str_to_bpe(extra)
+    bpe, numbers = prim_to_bpe(extra)
     bpe += [-1] * (xs.size(1) - len(bpe))
     bpe = torch.LongTensor([bpe])
 
@@ -56,6 +56,11 @@ for name, gl, dec, stype in [sec_info, aux_info]:
         y_words = [gl.bpe_to_str([word]) for word in y]
         hy_words = [gl.bpe_to_str([word]) for word in hard_out]
 
+        # Only the numbers of your own phrase are known, the dataset pairs keep their number tokens
+        if i == len(xs) - 1:
+            x_words = [unmask_numbers(word, numbers) for word in x_words]
+            hy_words = [unmask_numbers(word, numbers) for word in hy_words]
+
         print()
         print("/".join(hy_words), " <- ", "/".join(x_words))
         print("/".join(y_words))
@@ -0,0 +1,486 @@
+// Writes tables in the Arrow IPC file format, which pyarrow, pandas and polars read directly.
+// See https://arrow.apache.org/docs/format/Columnar.html#ipc-file-format
+
+// A file is:
+// - the magic string "ARROW1", padded to 8 bytes
+// - the schema message, then one record batch message per call to write_batch, then an end-of-stream marker
+// - the footer, which repeats the schema and says where each record batch is
+// - i32, the length of the footer, and the magic string again
+// Every message is the marker 0xFFFFFFFF, i32 the length of its metadata, the metadata and then its body, the column
+// buffers. The metadata and the footer are FlatBuffers, built with the small serializer below.
+
+// Only the column types the exports need are supported, all without nulls: u32, UTF-8 strings and lists of u32.
+
+use std::io::{Write, Result, Error, ErrorKind};
+use std::convert::TryInto;
+
+const MAGIC: &[u8; 6] = b"ARROW1";
+const CONTINUATION: u32 = 0xFFFF_FFFF;
+const ALIGNMENT: usize = 8;
+
+// MetadataVersion.V5
+const METADATA_VERSION: i16 = 4;
+
+// Members of the MessageHeader union
+const HEADER_SCHEMA: u8 = 1;
+const HEADER_RECORD_BATCH: u8 = 3;
+
+// Members of the Type union
+const TYPE_INT: u8 = 2;
+const TYPE_UTF8: u8 = 5;
+const TYPE_LIST: u8 = 12;
+
+// A FlatBuffers table, as its fields by index in the schema. Absent fields take their defaults.
+struct Table(Vec<(u16, Value)>);
+
+enum Value {
+    U8(u8),
+    Bool(bool),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    Table(Table),
+    String(String),
+    Tables(Vec<Table>),
+    // A vector of structs, as their count and their little-endian bytes. All structs here are 8-byte aligned.
+    Structs(usize, Vec<u8>),
+}
+
+impl Value {
+    fn inline_size(&self) -> usize {
+        match self {
+            Value::U8(_) | Value::Bool(_) => 1,
+            Value::I16(_) => 2,
+            Value::I64(_) => 8,
+            // Everything else is an i32 or an offset
+            _ => 4,
+        }
+    }
+}
+
+// Serializes from front to back: each object is written before the objects it refers to, so that all offsets point
+// forward like FlatBuffers requires, and the offsets are filled in once their targets are written.
+struct Builder {
+    buf: Vec<u8>,
+}
+
+impl Builder {
+    // Pads so that the position plus `offset` is aligned
+    fn align(&mut self, alignment: usize, offset: usize) {
+        while !(self.buf.len() + offset).is_multiple_of(alignment) {
+            self.buf.push(0);
+        }
+    }
+
+    fn set_offset(&mut self, slot: usize, target: usize) {
+        let offset = (target - slot) as u32;
+        self.buf[slot..slot + 4].copy_from_slice(&offset.to_le_bytes());
+    }
+
+    fn table(&mut self, table: &Table) -> usize {
+        // Place the fields after the vtable offset, largest first so that there is little padding
+        let mut fields: Vec<&(u16, Value)> = table.0.iter().collect();
+        fields.sort_by_key(|(_, value)| std::cmp::Reverse(value.inline_size()));
+
+        let mut positions = Vec::new();
+        let mut size: usize = 4;
+        for (_, value) in &fields {
+            let field_size = value.inline_size();
+            size = size.div_ceil(field_size) * field_size;
+            positions.push(size);
+            size += field_size;
+        }
+
+        let n_slots = table.0.iter().map(|&(index, _)| index as usize + 1).max().unwrap_or(0);
+        let mut vtable = vec![0u16; 2 + n_slots];
+        vtable[0] = (2 * vtable.len()) as u16;
+        vtable[1] = size as u16;
+        for ((index, _), &position) in fields.iter().zip(&positions) {
+            vtable[2 + *index as usize] = position as u16;
+        }
+
+        self.align(2, 0);
+        let vtable_start = self.buf.len();
+        for entry in vtable {
+            self.buf.extend_from_slice(&entry.to_le_bytes());
+        }
+
+        // Aligning the table to 8 bytes aligns all its fields
+        self.align(8, 0);
+        let start = self.buf.len();
+        self.buf.extend_from_slice(&((start - vtable_start) as i32).to_le_bytes());
+        self.buf.resize(start + size, 0);
+
+        let mut children = Vec::new();
+        for ((_, value), &position) in fields.iter().zip(&positions) {
+            let at = start + position;
+            match value {
+                Value::U8(x) => self.buf[at] = *x,
+                Value::Bool(x) => self.buf[at] = *x as u8,
+                Value::I16(x) => self.buf[at..at + 2].copy_from_slice(&x.to_le_bytes()),
+                Value::I32(x) => self.buf[at..at + 4].copy_from_slice(&x.to_le_bytes()),
+                Value::I64(x) => self.buf[at..at + 8].copy_from_slice(&x.to_le_bytes()),
+                object => children.push((at, object)),
+            }
+        }
+
+        for (slot, object) in children {
+            let target = self.object(object);
+            self.set_offset(slot, target);
+        }
+
+        start
+    }
+
+    fn object(&mut self, value: &Value) -> usize {
+        match value {
+            Value::Table(table) => self.table(table),
+            Value::String(s) => {
+                self.align(4, 0);
+                let start = self.buf.len();
+                self.buf.extend_from_slice(&(s.len() as u32).to_le_bytes());
+                self.buf.extend_from_slice(s.as_bytes());
+                self.buf.push(0);
+                start
+            }
+            Value::Tables(tables) => {
+                self.align(4, 0);
+                let start = self.buf.len();
+                self.buf.extend_from_slice(&(tables.len() as u32).to_le_bytes());
+                self.buf.resize(start + 4 + 4 * tables.len(), 0);
+                for (i, table) in tables.iter().enumerate() {
+                    let target = self.table(table);
+                    self.set_offset(start + 4 + 4 * i, target);
+                }
+                start
+            }
+            Value::Structs(count, bytes) => {
+                // The structs come right after the length, so the length goes 4 bytes before an aligned position
+                self.align(8, 4);
+                let start = self.buf.len();
+                self.buf.extend_from_slice(&(*count as u32).to_le_bytes());
+                self.buf.extend_from_slice(bytes);
+                start
+            }
+            _ => unreachable!("scalars are written inline"),
+        }
+    }
+
+    // The bytes of a FlatBuffer with the given root table, padded to a multiple of 8 bytes
+    fn finish(root: &Table) -> Vec<u8> {
+        let mut builder = Builder { buf: vec![0; 4] };
+        let start = builder.table(root);
+        builder.set_offset(0, start);
+        builder.align(8, 0);
+        builder.buf
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DataType {
+    UInt32,
+    Utf8,
+    ListUInt32,
+}
+
+pub struct Field {
+    pub name: &'static str,
+    pub data_type: DataType,
+}
+
+// The values of one column of a record batch, which must match the type of its field
+pub enum Column<'a> {
+    UInt32(Vec<u32>),
+    Utf8(Vec<&'a str>),
+    ListUInt32(Vec<&'a [usize]>),
+}
+
+fn type_table(data_type: DataType) -> (u8, Table) {
+    match data_type {
+        DataType::UInt32 => (TYPE_INT, Table(vec![(0, Value::I32(32)), (1, Value::Bool(false))])),
+        DataType::Utf8 => (TYPE_UTF8, Table(vec![])),
+        DataType::ListUInt32 => (TYPE_LIST, Table(vec![])),
+    }
+}
+
+fn field_table(name: &str, data_type: DataType) -> Table {
+    let children = match data_type {
+        DataType::ListUInt32 => vec![field_table("item", DataType::UInt32)],
+        _ => vec![],
+    };
+    let (type_type, type_) = type_table(data_type);
+    Table(vec![
+        (0, Value::String(name.to_string())),
+        (1, Value::Bool(false)),
+        (2, Value::U8(type_type)),
+        (3, Value::Table(type_)),
+        (5, Value::Tables(children)),
+    ])
+}
+
+fn schema_table(fields: &[Field]) -> Table {
+    Table(vec![(1, Value::Tables(fields.iter().map(|field| field_table(field.name, field.data_type)).collect()))])
+}
+
+fn message_table(header_type: u8, header: Table, body_length: usize) -> Table {
+    Table(vec![
+        (0, Value::I16(METADATA_VERSION)),
+        (1, Value::U8(header_type)),
+        (2, Value::Table(header)),
+        (3, Value::I64(body_length as i64)),
+    ])
+}
+
+// Where a message is in the file, for the footer
+struct Block {
+    offset: u64,
+    metadata_length: u32,
+    body_length: u64,
+}
+
+// The body of a record batch: its buffers back to back, each padded to 8 bytes, and the FieldNode and Buffer structs
+// that describe them
+#[derive(Default)]
+struct Body {
+    data: Vec<u8>,
+    nodes: Vec<u8>,
+    n_nodes: usize,
+    buffers: Vec<u8>,
+    n_buffers: usize,
+}
+
+impl Body {
+    fn node(&mut self, length: usize) {
+        self.nodes.extend_from_slice(&(length as i64).to_le_bytes());
+        // The null count
+        self.nodes.extend_from_slice(&0i64.to_le_bytes());
+        self.n_nodes += 1;
+    }
+
+    fn buffer(&mut self, bytes: &[u8]) {
+        self.buffers.extend_from_slice(&(self.data.len() as i64).to_le_bytes());
+        self.buffers.extend_from_slice(&(bytes.len() as i64).to_le_bytes());
+        self.n_buffers += 1;
+
+        self.data.extend_from_slice(bytes);
+        while !self.data.len().is_multiple_of(ALIGNMENT) {
+            self.data.push(0);
+        }
+    }
+
+    // Without nulls, the validity bitmap can be left out, but its buffer must still be listed
+    fn no_validity(&mut self) {
+        self.buffer(&[]);
+    }
+
+    fn u32_values<I: Iterator<Item=u32>>(&mut self, values: I) {
+        let bytes: Vec<u8> = values.flat_map(|value| value.to_le_bytes().to_vec()).collect();
+        self.buffer(&bytes);
+    }
+
+    // Arrow's default string and list types have i32 offsets
+    fn offsets<I: Iterator<Item=usize>>(&mut self, lengths: I) -> Result<usize> {
+        let mut offsets = vec![0i32];
+        let mut total = 0usize;
+        for length in lengths {
+            total += length;
+            let offset: i32 = total.try_into().map_err(|_| Error::new(ErrorKind::InvalidData, "column is too large for 32-bit Arrow offsets"))?;
+            offsets.push(offset);
+        }
+        let bytes: Vec<u8> = offsets.iter().flat_map(|offset| offset.to_le_bytes().to_vec()).collect();
+        self.buffer(&bytes);
+        Ok(total)
+    }
+
+    fn column(&mut self, column: &Column) -> Result<()> {
+        match column {
+            Column::UInt32(values) => {
+                self.node(values.len());
+                self.no_validity();
+                self.u32_values(values.iter().cloned());
+            }
+            Column::Utf8(values) => {
+                self.node(values.len());
+                self.no_validity();
+                self.offsets(values.iter().map(|s| s.len()))?;
+                let bytes: Vec<u8> = values.iter().flat_map(|s| s.bytes()).collect();
+                self.buffer(&bytes);
+            }
+            Column::ListUInt32(values) => {
+                self.node(values.len());
+                self.no_validity();
+                let total = self.offsets(values.iter().map(|list| list.len()))?;
+                self.node(total);
+                self.no_validity();
+                self.u32_values(values.iter().flat_map(|list| list.iter().map(|&value| value as u32)));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct Writer<W: Write> {
+    out: W,
+    position: u64,
+    fields: Vec<Field>,
+    batches: Vec<Block>,
+}
+
+impl<W: Write> Writer<W> {
+    // Starts a file with the given columns
+    pub fn new(out: W, fields: Vec<Field>) -> Result<Writer<W>> {
+        let mut writer = Writer { out, position: 0, fields, batches: Vec::new() };
+        writer.write(MAGIC)?;
+        writer.write(&[0, 0])?;
+
+        let schema = Builder::finish(&message_table(HEADER_SCHEMA, schema_table(&writer.fields), 0));
+        writer.write_message(&schema, &[])?;
+        Ok(writer)
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.out.write_all(bytes)?;
+        self.position += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn write_message(&mut self, metadata: &[u8], body: &[u8]) -> Result<Block> {
+        let block = Block { offset: self.position, metadata_length: 8 + metadata.len() as u32, body_length: body.len() as u64 };
+        self.write(&CONTINUATION.to_le_bytes())?;
+        self.write(&(metadata.len() as i32).to_le_bytes())?;
+        self.write(metadata)?;
+        self.write(body)?;
+        Ok(block)
+    }
+
+    // Writes a record batch of n_rows rows, with one column per field
+    pub fn write_batch(&mut self, n_rows: usize, columns: &[Column]) -> Result<()> {
+        assert_eq!(columns.len(), self.fields.len());
+
+        let mut body = Body::default();
+        for column in columns {
+            body.column(column)?;
+        }
+
+        let record_batch = Table(vec![
+            (0, Value::I64(n_rows as i64)),
+            (1, Value::Structs(body.n_nodes, body.nodes)),
+            (2, Value::Structs(body.n_buffers, body.buffers)),
+        ]);
+        let metadata = Builder::finish(&message_table(HEADER_RECORD_BATCH, record_batch, body.data.len()));
+        let block = self.write_message(&metadata, &body.data)?;
+        self.batches.push(block);
+        Ok(())
+    }
+
+    // Writes the end-of-stream marker and the footer, and returns the output
+    pub fn finish(mut self) -> Result<W> {
+        self.write(&CONTINUATION.to_le_bytes())?;
+        self.write(&0i32.to_le_bytes())?;
+
+        let mut blocks = Vec::new();
+        for block in &self.batches {
+            blocks.extend_from_slice(&(block.offset as i64).to_le_bytes());
+            blocks.extend_from_slice(&(block.metadata_length as i32).to_le_bytes());
+            blocks.extend_from_slice(&[0; 4]);
+            blocks.extend_from_slice(&(block.body_length as i64).to_le_bytes());
+        }
+
+        let footer = Builder::finish(&Table(vec![
+            (0, Value::I16(METADATA_VERSION)),
+            (1, Value::Table(schema_table(&self.fields))),
+            (3, Value::Structs(self.batches.len(), blocks)),
+        ]));
+        self.write(&footer)?;
+        self.write(&(footer.len() as i32).to_le_bytes())?;
+        self.write(MAGIC)?;
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(buf: &[u8], at: usize) -> usize {
+        u16::from_le_bytes(buf[at..at + 2].try_into().unwrap()) as usize
+    }
+
+    fn u32_at(buf: &[u8], at: usize) -> usize {
+        u32::from_le_bytes(buf[at..at + 4].try_into().unwrap()) as usize
+    }
+
+    fn i64_at(buf: &[u8], at: usize) -> i64 {
+        i64::from_le_bytes(buf[at..at + 8].try_into().unwrap())
+    }
+
+    // Where a field of a FlatBuffers table is, going through its vtable
+    fn field(buf: &[u8], table: usize, index: usize) -> usize {
+        let vtable = table - i32::from_le_bytes(buf[table..table + 4].try_into().unwrap()) as usize;
+        assert!(4 + 2 * index < u16_at(buf, vtable), "field {} is missing", index);
+        table + u16_at(buf, vtable + 4 + 2 * index)
+    }
+
+    // The table, string or vector an offset field points to
+    fn follow(buf: &[u8], at: usize) -> usize {
+        at + u32_at(buf, at)
+    }
+
+    #[test]
+    fn two_rows_have_the_arrow_file_layout() -> Result<()> {
+        let fields = vec![
+            Field { name: "id", data_type: DataType::UInt32 },
+            Field { name: "text", data_type: DataType::Utf8 },
+            Field { name: "tokens", data_type: DataType::ListUInt32 },
+        ];
+        let tokens: [&[usize]; 2] = [&[1, 2, 3], &[4]];
+        let mut writer = Writer::new(Vec::new(), fields)?;
+        writer.write_batch(2, &[
+            Column::UInt32(vec![7, 9]),
+            Column::Utf8(vec!["a", "bc"]),
+            Column::ListUInt32(tokens.to_vec()),
+        ])?;
+        let file = writer.finish()?;
+
+        assert_eq!(&file[..8], b"ARROW1\0\0");
+        assert_eq!(&file[file.len() - 6..], MAGIC);
+        assert_eq!(u32_at(&file, 8), CONTINUATION as usize);
+        assert_eq!(u32_at(&file, 12) % ALIGNMENT, 0);
+
+        // The footer comes right after the end-of-stream marker, and lists the only record batch
+        let footer_len = u32_at(&file, file.len() - 10);
+        let footer = &file[file.len() - 10 - footer_len..file.len() - 10];
+        assert_eq!(&file[file.len() - 18 - footer_len..file.len() - 10 - footer_len], &[0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0]);
+
+        let blocks = follow(footer, field(footer, u32_at(footer, 0), 3));
+        assert_eq!(u32_at(footer, blocks), 1);
+        let offset = i64_at(footer, blocks + 4) as usize;
+        let metadata_len = u32_at(footer, blocks + 12);
+        let body_len = i64_at(footer, blocks + 20) as usize;
+
+        // The record batch message at that offset: its metadata, then its body
+        assert_eq!(u32_at(&file, offset), CONTINUATION as usize);
+        assert_eq!(u32_at(&file, offset + 4), metadata_len - 8);
+        let metadata = &file[offset + 8..offset + metadata_len];
+        let message = u32_at(metadata, 0);
+        assert_eq!(metadata[field(metadata, message, 1)], HEADER_RECORD_BATCH);
+        assert_eq!(i64_at(metadata, field(metadata, message, 3)), body_len as i64);
+        let record_batch = follow(metadata, field(metadata, message, 2));
+        assert_eq!(i64_at(metadata, field(metadata, record_batch, 0)), 2);
+
+        // Every buffer starts on 8 bytes, and validity bitmaps are empty
+        let mut expected = Vec::new();
+        let mut pad = |bytes: &[u8]| {
+            expected.extend_from_slice(bytes);
+            expected.resize(expected.len().div_ceil(ALIGNMENT) * ALIGNMENT, 0);
+        };
+        pad(&[7, 0, 0, 0, 9, 0, 0, 0]);
+        pad(&[0, 0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0]);
+        pad(b"abc");
+        pad(&[0, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0]);
+        pad(&[1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0]);
+        assert_eq!(&file[offset + metadata_len..offset + metadata_len + body_len], &expected[..]);
+
+        Ok(())
+    }
+}
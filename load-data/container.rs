@@ -0,0 +1,175 @@
+// Packs all files of a build into one container file, so that a build can be moved around as a whole and files
+// from different builds can't be mixed up.
+
+// Container format, all numbers little-endian:
+// - a header (see format.rs) of kind Container, whose count is the number of sections
+// - the table of contents, one 56 byte entry per section:
+//   offset: 0x00 0x20   0x28   0x30     0x34
+//    value: name offset length checksum padding
+//   name:     32 bytes, the file name of the section ("sentences-prim.bin"), padded with zeros
+//   offset:   u64, where the section starts, counted from the start of the container
+//   length:   u64, the length of the section in bytes
+//   checksum: u32, the CRC-32 of the section (the same as zlib's crc32)
+// - the sections, each starting at a multiple of 8 bytes. Each section is an unchanged copy of the file it was
+//   made from, header included.
+
+use std::io::{Read, Write, Seek, SeekFrom, Result, Error, ErrorKind, BufReader, BufWriter, copy, sink};
+use std::fs::File;
+use std::convert::TryInto;
+
+use crate::format::{Header, Kind, HEADER_SIZE};
+use crate::hash::Crc32;
+
+pub const ENTRY_SIZE: u64 = 0x38;
+const NAME_SIZE: usize = 32;
+const ALIGNMENT: u64 = 8;
+
+#[derive(Debug, Clone)]
+pub struct Section {
+    pub name: String,
+    pub offset: u64,
+    pub length: u64,
+    pub checksum: u32,
+}
+
+fn align(offset: u64) -> u64 {
+    offset.div_ceil(ALIGNMENT) * ALIGNMENT
+}
+
+// Computes the checksum while copying
+struct ChecksumWriter<W> {
+    inner: W,
+    crc: Crc32,
+}
+
+impl<W: Write> Write for ChecksumWriter<W> {
+    fn write(&mut self, bytes: &[u8]) -> Result<usize> {
+        let n = self.inner.write(bytes)?;
+        self.crc.write(&bytes[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+// Writes the files, given as (section name, path), into a new container at path
+pub fn write_container(path: &str, fingerprint: u64, files: &[(String, String)]) -> Result<Vec<Section>> {
+    let mut sections = Vec::new();
+    let mut offset = align(HEADER_SIZE + files.len() as u64 * ENTRY_SIZE);
+    for (name, file_path) in files {
+        if name.len() > NAME_SIZE {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("section name {:?} is too long", name)));
+        }
+        let length = std::fs::metadata(file_path)?.len();
+        sections.push(Section { name: name.clone(), offset, length, checksum: 0 });
+        offset = align(offset + length);
+    }
+
+    let mut out = BufWriter::new(File::create(path)?);
+    let header = Header { kind: Kind::Container, width: 8, language: String::new(), count: sections.len() as u64, fingerprint };
+    header.write(&mut out)?;
+
+    // The checksums are filled in once the sections have been copied
+    let toc_end = HEADER_SIZE + sections.len() as u64 * ENTRY_SIZE;
+    out.write_all(&vec![0u8; (toc_end - HEADER_SIZE) as usize])?;
+
+    let mut position = toc_end;
+    for (section, (_, file_path)) in sections.iter_mut().zip(files.iter()) {
+        out.write_all(&vec![0u8; (section.offset - position) as usize])?;
+
+        let mut writer = ChecksumWriter { inner: &mut out, crc: Crc32::new() };
+        let copied = copy(&mut BufReader::new(File::open(file_path)?), &mut writer)?;
+        if copied != section.length {
+            return Err(Error::new(ErrorKind::InvalidData, format!("{} changed while it was being packed", file_path)));
+        }
+        section.checksum = writer.crc.finish();
+        position = section.offset + section.length;
+    }
+
+    let mut file = out.into_inner().map_err(|e| e.into_error())?;
+    file.seek(SeekFrom::Start(HEADER_SIZE))?;
+    let mut toc = BufWriter::new(file);
+    for section in &sections {
+        let mut name = [0u8; NAME_SIZE];
+        name[..section.name.len()].copy_from_slice(section.name.as_bytes());
+        toc.write_all(&name)?;
+        toc.write_all(&section.offset.to_le_bytes())?;
+        toc.write_all(&section.length.to_le_bytes())?;
+        toc.write_all(&section.checksum.to_le_bytes())?;
+        toc.write_all(&[0u8; 4])?;
+    }
+    toc.flush()?;
+
+    Ok(sections)
+}
+
+pub struct Container {
+    pub header: Header,
+    pub sections: Vec<Section>,
+    file: File,
+}
+
+impl Container {
+    // Reads the header and the table of contents, checking that all sections lie within the file
+    pub fn open(path: &str) -> Result<Container> {
+        let with_path = |e: Error| Error::new(e.kind(), format!("{}: {}", path, e));
+
+        let file = File::open(path).map_err(with_path)?;
+        let size = file.metadata().map_err(with_path)?.len();
+        let mut reader = BufReader::new(file);
+
+        let header = Header::read(&mut reader).map_err(with_path)?;
+        if header.kind != Kind::Container {
+            return Err(with_path(Error::new(ErrorKind::InvalidData, format!("expected {:?}, found {:?}", Kind::Container, header.kind))));
+        }
+        if header.file_size() > size {
+            return Err(with_path(Error::new(ErrorKind::InvalidData, "the table of contents is cut off")));
+        }
+
+        let mut sections = Vec::new();
+        for _ in 0..header.count {
+            let mut entry = [0u8; ENTRY_SIZE as usize];
+            reader.read_exact(&mut entry).map_err(with_path)?;
+
+            let name_bytes: Vec<u8> = entry[0x00..0x20].iter().cloned().take_while(|&b| b != 0).collect();
+            let name = String::from_utf8(name_bytes).map_err(|_| with_path(Error::new(ErrorKind::InvalidData, "invalid section name")))?;
+            let offset = u64::from_le_bytes(entry[0x20..0x28].try_into().unwrap());
+            let length = u64::from_le_bytes(entry[0x28..0x30].try_into().unwrap());
+            let checksum = u32::from_le_bytes(entry[0x30..0x34].try_into().unwrap());
+
+            if offset.checked_add(length).is_none_or(|end| end > size) {
+                return Err(with_path(Error::new(ErrorKind::InvalidData, format!("section {} lies outside of the file", name))));
+            }
+            sections.push(Section { name, offset, length, checksum });
+        }
+
+        Ok(Container { header, sections, file: reader.into_inner() })
+    }
+
+    // Copies a section to out, failing if its checksum doesn't match
+    pub fn copy_section<W: Write>(&mut self, section: &Section, out: &mut W) -> Result<()> {
+        self.file.seek(SeekFrom::Start(section.offset))?;
+
+        let mut writer = ChecksumWriter { inner: out, crc: Crc32::new() };
+        copy(&mut BufReader::new((&self.file).take(section.length)), &mut writer)?;
+
+        let checksum = writer.crc.finish();
+        if checksum != section.checksum {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("section {} is corrupt (checksum {:08x}, expected {:08x})", section.name, checksum, section.checksum),
+            ));
+        }
+        Ok(())
+    }
+
+    // Checks the checksums of all sections
+    pub fn verify(&mut self) -> Result<()> {
+        for section in self.sections.clone() {
+            self.copy_section(&section, &mut sink())?;
+        }
+        Ok(())
+    }
+}
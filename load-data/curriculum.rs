@@ -0,0 +1,76 @@
+// Scores how difficult each link is, for curriculum learning: short pairs of common grams with similar lengths first.
+// The score is a weighted sum of three features, each divided by its mean over the links so the weights are comparable:
+//  * length: the mean number of tokens of the two sentences
+//  * rarity: the mean surprisal of the tokens in the two sentences
+//  * ratio: how different the lengths of the two sentences are, as |ln(len_prim / len_other)|
+
+use std::collections::HashMap;
+
+pub struct Weights {
+    pub length: f64,
+    pub rarity: f64,
+    pub ratio: f64,
+}
+
+// -ln of the relative frequency of each gram in the sentences, with add-one smoothing
+pub fn gram_surprisal<'a, I: IntoIterator<Item=&'a Vec<usize>>>(sents: I, n_grams: usize) -> Vec<f64> {
+    let mut counts = vec![1usize; n_grams];
+    for sent in sents {
+        for &token in sent {
+            counts[token] += 1;
+        }
+    }
+
+    let total: usize = counts.iter().sum();
+    counts.into_iter().map(|count| -(count as f64 / total as f64).ln()).collect()
+}
+
+fn mean_surprisal(sent: &[usize], surprisal: &[f64]) -> f64 {
+    if sent.is_empty() {
+        return 0.0;
+    }
+    sent.iter().map(|&token| surprisal[token]).sum::<f64>() / sent.len() as f64
+}
+
+fn normalize(values: &mut [f64]) {
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    if mean > 0.0 {
+        values.iter_mut().for_each(|x| *x /= mean);
+    }
+}
+
+// The difficulty of each link, in the same order
+pub fn difficulty(
+    links: &[(u32, u32)],
+    prim: &HashMap<u32, Vec<usize>>, other: &HashMap<u32, Vec<usize>>,
+    prim_surprisal: &[f64], other_surprisal: &[f64],
+    weights: &Weights,
+) -> Vec<f64> {
+    let mut lengths = Vec::with_capacity(links.len());
+    let mut rarities = Vec::with_capacity(links.len());
+    let mut ratios = Vec::with_capacity(links.len());
+
+    for (prim_id, other_id) in links {
+        let (prim_sent, other_sent) = (&prim[prim_id], &other[other_id]);
+
+        lengths.push((prim_sent.len() + other_sent.len()) as f64 / 2.0);
+        rarities.push((mean_surprisal(prim_sent, prim_surprisal) + mean_surprisal(other_sent, other_surprisal)) / 2.0);
+        ratios.push(((prim_sent.len().max(1) as f64) / (other_sent.len().max(1) as f64)).ln().abs());
+    }
+
+    normalize(&mut lengths);
+    normalize(&mut rarities);
+    normalize(&mut ratios);
+
+    (0..links.len())
+        .map(|i| weights.length * lengths[i] + weights.rarity * rarities[i] + weights.ratio * ratios[i])
+        .collect()
+}
+
+// Sorts the links from easiest to hardest, along with their scores
+pub fn sort_by_difficulty(links: &[(u32, u32)], scores: &[f64]) -> (Vec<(u32, u32)>, Vec<f64>) {
+    let mut order: Vec<usize> = (0..links.len()).collect();
+    order.sort_by(|&a, &b| scores[a].partial_cmp(&scores[b]).unwrap().then(links[a].cmp(&links[b])));
+
+    (order.iter().map(|&i| links[i]).collect(), order.iter().map(|&i| scores[i]).collect())
+}
@@ -0,0 +1,298 @@
+// Filters which remove or clean up sentences before they are gramified.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{Result, Error, ErrorKind, BufReader, BufRead};
+use std::fs::File;
+
+// Unicode scripts are approximated by their main blocks, which is enough to catch stray characters.
+// Common only covers digits, punctuation and whitespace, not symbols like emoji (which Unicode also counts as common).
+#[allow(unused)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Script {
+    Common,
+    Latin,
+    Greek,
+    Cyrillic,
+    Han,
+    Kana,
+}
+
+impl Script {
+    pub fn contains(self, ch: char) -> bool {
+        let c = ch as u32;
+        match self {
+            Script::Common =>
+                ch.is_whitespace()
+                || ch.is_ascii_digit()
+                || ch.is_ascii_punctuation()
+                || (0xA0..=0xBF).contains(&c) // Latin-1 punctuation, like ¡ and ¿
+                || c == 0xD7 || c == 0xF7
+                || (0x2000..=0x206F).contains(&c), // General punctuation, like “ and …
+            Script::Latin =>
+                ch.is_ascii_alphabetic()
+                || ((0xC0..=0x24F).contains(&c) && c != 0xD7 && c != 0xF7)
+                || (0x1E00..=0x1EFF).contains(&c),
+            Script::Greek => (0x370..=0x3FF).contains(&c) || (0x1F00..=0x1FFF).contains(&c),
+            Script::Cyrillic => (0x400..=0x52F).contains(&c),
+            Script::Han => (0x4E00..=0x9FFF).contains(&c) || (0x3000..=0x303F).contains(&c),
+            Script::Kana => (0x3040..=0x30FF).contains(&c),
+        }
+    }
+}
+
+// The characters a language may contain: everything in one of the scripts, plus some extra characters
+pub struct CharSet {
+    pub scripts: &'static [Script],
+    pub extra: &'static str,
+}
+
+impl CharSet {
+    pub fn allows(&self, ch: char) -> bool {
+        self.scripts.iter().any(|script| script.contains(ch)) || self.extra.contains(ch)
+    }
+}
+
+// What to do with a sentence containing characters outside of its language's character set
+#[allow(unused)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CharPolicy {
+    // Remove the whole sentence
+    Drop,
+    // Remove only the offending characters
+    Strip,
+    // Replace each offending character with UNKNOWN_TOKEN
+    Unknown,
+}
+
+// Like the number tokens, taken from the private use area so it can't collide with real text
+pub const UNKNOWN_TOKEN: char = '\u{E0FF}';
+
+pub fn reserved_tokens(policy: CharPolicy) -> Vec<char> {
+    match policy {
+        CharPolicy::Unknown => vec![UNKNOWN_TOKEN],
+        _ => Vec::new(),
+    }
+}
+
+// How often each rejected character occurred, and the smallest ID of a sentence it occurred in, so that the report is
+// the same for the same input
+#[derive(Debug, Default)]
+pub struct CharReport {
+    pub counts: HashMap<char, (usize, u32)>,
+    pub n_sentences: usize,
+}
+
+impl CharReport {
+    fn add(&mut self, ch: char, id: u32) {
+        let entry = self.counts.entry(ch).or_insert((0, id));
+        entry.0 += 1;
+        entry.1 = entry.1.min(id);
+    }
+
+    // Most common characters first
+    pub fn sorted(&self) -> Vec<(char, usize, u32)> {
+        let mut chars: Vec<_> = self.counts.iter().map(|(&ch, &(count, id))| (ch, count, id)).collect();
+        chars.sort_by_key(|&(ch, count, _)| (std::cmp::Reverse(count), ch));
+        chars
+    }
+}
+
+// Applies the policy to all sentences in place. Returns the IDs of the sentences which should be dropped.
+pub fn filter_chars(sents: &mut HashMap<u32, String>, allowed: &CharSet, policy: CharPolicy, report: &mut CharReport) -> Vec<u32> {
+    let mut to_drop = Vec::new();
+
+    for (&id, sent) in sents.iter_mut() {
+        if sent.chars().all(|ch| allowed.allows(ch)) {
+            continue;
+        }
+
+        report.n_sentences += 1;
+        for ch in sent.chars().filter(|&ch| !allowed.allows(ch)) {
+            report.add(ch, id);
+        }
+
+        match policy {
+            CharPolicy::Drop => to_drop.push(id),
+            CharPolicy::Strip => {
+                *sent = sent.chars().filter(|&ch| allowed.allows(ch)).collect();
+                if sent.trim().is_empty() {
+                    to_drop.push(id);
+                }
+            }
+            CharPolicy::Unknown => {
+                *sent = sent.chars().map(|ch| if allowed.allows(ch) { ch } else { UNKNOWN_TOKEN }).collect();
+            }
+        }
+    }
+
+    for id in &to_drop {
+        sents.remove(id);
+    }
+
+    to_drop
+}
+
+// The longest sentences allowed in a language, measured in characters before and in tokens after gramifying
+pub struct LengthLimit {
+    pub max_chars: Option<usize>,
+    pub max_tokens: Option<usize>,
+}
+
+// Removes all sentences longer than max. Returns the number of removed sentences.
+pub fn filter_length<T, F: Fn(&T) -> usize>(sents: &mut HashMap<u32, T>, max: Option<usize>, len: F) -> usize {
+    let max = match max {
+        Some(max) => max,
+        None => return 0,
+    };
+
+    let n_sents = sents.len();
+    sents.retain(|_, sent| len(sent) <= max);
+    n_sents - sents.len()
+}
+
+// histogram[n] is the number of sentences of length n
+pub fn length_histogram<T, F: Fn(&T) -> usize>(sents: &HashMap<u32, T>, len: F) -> Vec<usize> {
+    let mut histogram = Vec::new();
+    for sent in sents.values() {
+        let n = len(sent);
+        if histogram.len() <= n {
+            histogram.resize(n + 1, 0);
+        }
+        histogram[n] += 1;
+    }
+    histogram
+}
+
+// The smallest length which at least the fraction q of all sentences fit in
+pub fn histogram_quantile(histogram: &[usize], q: f64) -> usize {
+    let total: usize = histogram.iter().sum();
+    let mut seen = 0;
+    for (n, &count) in histogram.iter().enumerate() {
+        seen += count;
+        if seen as f64 >= q * total as f64 {
+            return n;
+        }
+    }
+    histogram.len().saturating_sub(1)
+}
+
+// Prints the histogram with lengths grouped into buckets of bucket_size
+pub fn print_histogram(histogram: &[usize], bucket_size: usize) {
+    const BAR_WIDTH: usize = 50;
+
+    let buckets: Vec<usize> = histogram.chunks(bucket_size).map(|chunk| chunk.iter().sum()).collect();
+    let largest = buckets.iter().cloned().max().unwrap_or(0).max(1);
+
+    for (i, &count) in buckets.iter().enumerate() {
+        let bar = (count * BAR_WIDTH).div_ceil(largest);
+        println!("{:>4}-{:<4} {:>7} {}", i * bucket_size, (i + 1) * bucket_size - 1, count, "#".repeat(bar));
+    }
+}
+
+// A list of Tatoeba sentence IDs and links, read from a file with one ID, or two tab-separated IDs for a link, per line.
+// Empty lines and lines starting with # are ignored.
+#[derive(Debug, Default)]
+pub struct IdList {
+    pub sentences: HashSet<u32>,
+    pub links: HashSet<(u32, u32)>, // Always (smallest, largest), since links are undirected
+    pub link_ends: HashSet<u32>, // Both sentences of every link
+}
+
+impl IdList {
+    // A missing file gives an empty list
+    pub fn from_file(path: &str) -> Result<IdList> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(IdList::default()),
+            Err(e) => return Err(e),
+        };
+
+        let mut list = IdList::default();
+        for (line_nr, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let ids =
+                line
+                .split('\t')
+                .map(|id| id.trim().parse::<u32>())
+                .collect::<std::result::Result<Vec<_>, _>>()
+                .map_err(|_| Error::new(ErrorKind::InvalidData, format!("{}:{}: invalid sentence ID", path, line_nr + 1)))?;
+
+            match ids[..] {
+                [id] => { list.sentences.insert(id); }
+                [a, b] => list.insert_link(a, b),
+                _ => return Err(Error::new(ErrorKind::InvalidData, format!("{}:{}: expected one or two IDs", path, line_nr + 1))),
+            }
+        }
+
+        Ok(list)
+    }
+
+    pub fn insert_link(&mut self, a: u32, b: u32) {
+        self.links.insert((a.min(b), a.max(b)));
+        self.link_ends.insert(a);
+        self.link_ends.insert(b);
+    }
+
+    pub fn contains_link(&self, a: u32, b: u32) -> bool {
+        self.links.contains(&(a.min(b), a.max(b)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sentences.is_empty() && self.links.is_empty()
+    }
+}
+
+// Sentences and links have to be in the allowlist (unless it is empty) and not in the blocklist. Listing a link in
+// the allowlist also allows its sentences, and listing two sentences allows the links between them.
+#[derive(Debug, Default)]
+pub struct IdFilter {
+    pub block: IdList,
+    pub allow: IdList,
+}
+
+impl IdFilter {
+    pub fn allows_sentence(&self, id: u32) -> bool {
+        !self.block.sentences.contains(&id)
+            && (self.allow.is_empty() || self.allow.sentences.contains(&id) || self.allow.link_ends.contains(&id))
+    }
+
+    pub fn allows_link(&self, a: u32, b: u32) -> bool {
+        !self.block.contains_link(a, b)
+            && (
+                self.allow.is_empty()
+                || self.allow.contains_link(a, b)
+                || (self.allow.sentences.contains(&a) && self.allow.sentences.contains(&b))
+            )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowlist_mixes_sentences_and_links() {
+        let mut filter = IdFilter::default();
+        filter.allow.sentences.extend(&[1, 2, 5]);
+        filter.allow.insert_link(4, 3);
+        filter.block.insert_link(1, 5);
+
+        assert!([1, 2, 3, 4, 5].iter().all(|&id| filter.allows_sentence(id)));
+        assert!(!filter.allows_sentence(6));
+
+        // Listed links, in either direction, and links between listed sentences
+        assert!(filter.allows_link(3, 4) && filter.allows_link(4, 3));
+        assert!(filter.allows_link(2, 1));
+        // The end of a listed link doesn't allow its other links, and the blocklist still applies
+        assert!(!filter.allows_link(1, 3));
+        assert!(!filter.allows_link(1, 6));
+        assert!(!filter.allows_link(5, 1));
+
+        assert!(IdFilter::default().allows_sentence(6) && IdFilter::default().allows_link(1, 6));
+    }
+}
@@ -0,0 +1,187 @@
+// Every binary file written by select-langs starts with a header describing its contents, so that readers don't have
+// to guess sizes from file lengths, and so that files from different builds can't be mixed up silently.
+
+// Header format, all numbers little-endian:
+// offset: 0x00 0x04    0x06 0x07  0x08      0x18  0x20
+//  value: magic version kind width language  count fingerprint
+//
+// magic:       the bytes "ILPT"
+// version:     u16, the version of the file formats
+// kind:        u8, what the file contains, see Kind
+// width:       u8, the width in bytes of each number in the file (for sentences, the width of a token)
+// language:    16 bytes, the language code ("eng"), or two codes for pairs ("eng-toki"), padded with zeros
+// count:       u64, the number of records after the header
+// fingerprint: u64, identifies the build; all files from the same build have the same fingerprint
+
+use std::io::{Read, Write, Result, Error, ErrorKind, BufReader};
+use std::fs::File;
+
+pub const MAGIC: &[u8; 4] = b"ILPT";
+pub const VERSION: u16 = 1;
+pub const HEADER_SIZE: u64 = 0x28;
+
+const LANGUAGE_SIZE: usize = 16;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Kind {
+    // Gram tables, see tokens::encode_grams. Each record is a gram.
+    Grams = 1,
+    // Token streams. Each record is a token.
+    Sentences = 2,
+    // Links between sentences, see write_links. Each record is a link.
+    Links = 3,
+    // Length buckets, see sampling::write_buckets
+    Buckets = 4,
+    // Epoch permutations, see sampling::write_permutations. Each record is a link index.
+    Permutations = 5,
+    // 32-bit floats, one per link, see sampling::write_weights
+    Weights = 6,
+    // Cross-validation folds, one per link
+    Folds = 7,
+    // Tatoeba IDs of the sentences, in the order they appear in the sentence file
+    Ids = 8,
+    // A whole build in one file, see container.rs. Each record is an entry of the table of contents.
+    Container = 9,
+    // Token streams encoded as varints, see varint.rs. Each record is a byte.
+    Varints = 10,
+    // The gram of each frequency rank, for decoding varint token streams
+    Ranks = 11,
+    // Where each sentence starts in its sentence file in bytes, in the order of the IDs file, followed by the end
+    Index = 12,
+}
+
+impl Kind {
+    fn from_u8(kind: u8) -> Option<Kind> {
+        [
+            Kind::Grams, Kind::Sentences, Kind::Links, Kind::Buckets, Kind::Permutations, Kind::Weights, Kind::Folds, Kind::Ids,
+            Kind::Container, Kind::Varints, Kind::Ranks, Kind::Index,
+        ]
+            .iter()
+            .cloned()
+            .find(|&k| k as u8 == kind)
+    }
+
+    // How many numbers of the header's width each record consists of
+    fn numbers_per_record(self) -> u64 {
+        match self {
+            Kind::Links | Kind::Buckets => 4,
+            _ => 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub kind: Kind,
+    pub width: u8,
+    pub language: String,
+    pub count: u64,
+    pub fingerprint: u64,
+}
+
+impl Header {
+    pub fn write<F: Write>(&self, out: &mut F) -> Result<()> {
+        if self.language.len() > LANGUAGE_SIZE {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("language code {:?} is too long for the header", self.language)));
+        }
+        let mut language = [0u8; LANGUAGE_SIZE];
+        language[..self.language.len()].copy_from_slice(self.language.as_bytes());
+
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+        out.write_all(&[self.kind as u8, self.width])?;
+        out.write_all(&language)?;
+        out.write_all(&self.count.to_le_bytes())?;
+        out.write_all(&self.fingerprint.to_le_bytes())?;
+        Ok(())
+    }
+
+    pub fn read<R: Read>(inp: &mut R) -> Result<Header> {
+        let mut buf = [0u8; HEADER_SIZE as usize];
+        inp.read_exact(&mut buf).map_err(|_| Error::new(ErrorKind::InvalidData, "file is too short for a header"))?;
+
+        if &buf[0x00..0x04] != MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "not a select-langs file (wrong magic)"));
+        }
+
+        let version = u16::from_le_bytes([buf[0x04], buf[0x05]]);
+        if version != VERSION {
+            return Err(Error::new(ErrorKind::InvalidData, format!("format version {} is not supported (expected {})", version, VERSION)));
+        }
+
+        let kind = Kind::from_u8(buf[0x06]).ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("unknown file kind {}", buf[0x06])))?;
+
+        let language_bytes: Vec<u8> = buf[0x08..0x18].iter().cloned().take_while(|&b| b != 0).collect();
+        let language = String::from_utf8(language_bytes).map_err(|_| Error::new(ErrorKind::InvalidData, "invalid language code"))?;
+
+        let mut count = [0u8; 8];
+        count.copy_from_slice(&buf[0x18..0x20]);
+        let mut fingerprint = [0u8; 8];
+        fingerprint.copy_from_slice(&buf[0x20..0x28]);
+
+        Ok(Header {
+            kind,
+            width: buf[0x07],
+            language,
+            count: u64::from_le_bytes(count),
+            fingerprint: u64::from_le_bytes(fingerprint),
+        })
+    }
+
+    pub fn record_size(&self) -> u64 {
+        match self.kind {
+            // Tag byte and eight bytes of data
+            Kind::Grams => 9,
+            Kind::Container => crate::container::ENTRY_SIZE,
+            kind => kind.numbers_per_record() * self.width as u64,
+        }
+    }
+
+    // The size of the whole file, header included. For containers, only up to the end of the table of contents.
+    pub fn file_size(&self) -> u64 {
+        HEADER_SIZE + self.count * self.record_size()
+    }
+}
+
+// Opens a file written by select-langs, checking that it has the expected kind and that its size matches the header.
+// The reader is positioned right after the header.
+pub fn open(path: &str, kind: Kind) -> Result<(Header, BufReader<File>)> {
+    open_one_of(path, &[kind])
+}
+
+// Like open, for files which can have one of several kinds, like sentence files
+pub fn open_one_of(path: &str, kinds: &[Kind]) -> Result<(Header, BufReader<File>)> {
+    let with_path = |e: Error| Error::new(e.kind(), format!("{}: {}", path, e));
+
+    let file = File::open(path).map_err(with_path)?;
+    let size = file.metadata().map_err(with_path)?.len();
+    let mut reader = BufReader::new(file);
+
+    let header = Header::read(&mut reader).map_err(with_path)?;
+    if !kinds.contains(&header.kind) {
+        return Err(with_path(Error::new(ErrorKind::InvalidData, format!("expected {:?}, found {:?}", kinds, header.kind))));
+    }
+    if header.file_size() != size {
+        return Err(with_path(Error::new(
+            ErrorKind::InvalidData,
+            format!("header describes {} bytes, but the file has {}", header.file_size(), size),
+        )));
+    }
+
+    Ok((header, reader))
+}
+
+// Checks that all headers come from the same build
+pub fn check_same_build(headers: &[(&str, &Header)]) -> Result<()> {
+    if let Some(&(first_path, first)) = headers.first() {
+        for &(path, header) in headers {
+            if header.fingerprint != first.fingerprint {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("{} and {} are from different builds ({:016x} and {:016x})", first_path, path, first.fingerprint, header.fingerprint),
+                ));
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,84 @@
+// Hashes which have to stay the same between builds and Rust versions, which std's hashers don't promise
+
+use std::io::{Write, Result};
+
+// The SplitMix64 finalizer
+pub fn mix64(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+// Maps an ID to a number uniformly distributed in [0, 1), which only depends on the ID and the key
+pub fn keyed_unit(key: u64, id: u32) -> f64 {
+    let h = mix64(mix64(key) ^ id as u64);
+    (h >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// FNV-1a over everything written, with the SplitMix64 finalizer on top to spread the bits
+pub struct Hasher64 {
+    state: u64,
+}
+
+impl Hasher64 {
+    pub fn new() -> Hasher64 {
+        Hasher64 { state: 0xCBF29CE484222325 }
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.state ^= byte as u64;
+            self.state = self.state.wrapping_mul(0x100000001B3);
+        }
+    }
+
+    pub fn write_u64(&mut self, x: u64) {
+        self.write(&x.to_le_bytes());
+    }
+
+    pub fn finish(&self) -> u64 {
+        mix64(self.state)
+    }
+}
+
+// So that anything which can be written to a file can also be hashed
+impl Write for Hasher64 {
+    fn write(&mut self, bytes: &[u8]) -> Result<usize> {
+        Hasher64::write(self, bytes);
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// CRC-32 as used by zlib, so that checksums can be checked with Python's zlib.crc32
+pub struct Crc32 {
+    table: [u32; 256],
+    state: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Crc32 {
+        let mut table = [0u32; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            let mut c = i as u32;
+            for _ in 0..8 {
+                c = if c & 1 != 0 { 0xEDB88320 ^ (c >> 1) } else { c >> 1 };
+            }
+            *entry = c;
+        }
+        Crc32 { table, state: 0xFFFFFFFF }
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.state = self.table[((self.state ^ byte as u32) & 0xFF) as usize] ^ (self.state >> 8);
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        self.state ^ 0xFFFFFFFF
+    }
+}
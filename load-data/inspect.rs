@@ -0,0 +1,32 @@
+// Prints pairs of a build for debugging, with their token boundaries like display_tokens in train.py:
+//   sec-links-train.bin link 12
+//   eng 1276: let/'s/ tr/y/ some/thing/.
+//   toki 2254917: o/ pali/ e/ ijo/.
+// Private use characters, like masked numbers, are shown as <U+E000>.
+
+use std::io::Result;
+
+use crate::reader::{Pair, Sentence};
+
+fn describe(sentence: &Sentence) -> Result<String> {
+    let id = match sentence.id {
+        Some(id) => id.to_string(),
+        None => "?".to_string(),
+    };
+    let pieces: Vec<String> =
+        sentence
+        .pieces()?
+        .into_iter()
+        .map(|piece| piece.chars().map(|ch| if ('\u{E000}'..='\u{F8FF}').contains(&ch) { format!("<U+{:04X}>", ch as u32) } else { ch.to_string() }).collect())
+        .collect();
+
+    Ok(format!("{} {}: {}", sentence.language.code, id, pieces.join("/")))
+}
+
+pub fn print_pair(title: &str, pair: &Pair) -> Result<()> {
+    println!("{}", title);
+    println!("{}", describe(&pair.prim)?);
+    println!("{}", describe(&pair.other)?);
+    println!();
+    Ok(())
+}
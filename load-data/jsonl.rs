@@ -0,0 +1,53 @@
+// Writes pairs as JSON Lines, for looking at the data and for feeding it to other toolkits. Each line is one link:
+// {"split": "train", "prim": {"id": 1276, "language": "eng", "text": "Let's try something.", "tokens": [12, 40, ...]},
+//  "other": {"id": 2254917, "language": "toki", "text": "o pali e ijo.", "tokens": [7, 31, ...]}}
+
+use std::io::{Write, Result};
+
+pub struct Side<'a> {
+    pub id: u32,
+    pub language: &'a str,
+    pub text: &'a str,
+    pub tokens: &'a [usize],
+}
+
+fn write_string<W: Write>(out: &mut W, s: &str) -> Result<()> {
+    write!(out, "\"")?;
+    for ch in s.chars() {
+        match ch {
+            '"' => write!(out, "\\\"")?,
+            '\\' => write!(out, "\\\\")?,
+            '\n' => write!(out, "\\n")?,
+            '\r' => write!(out, "\\r")?,
+            '\t' => write!(out, "\\t")?,
+            ch if (ch as u32) < 0x20 => write!(out, "\\u{:04x}", ch as u32)?,
+            ch => write!(out, "{}", ch)?,
+        }
+    }
+    write!(out, "\"")
+}
+
+fn write_side<W: Write>(out: &mut W, side: &Side) -> Result<()> {
+    write!(out, "{{\"id\": {}, \"language\": ", side.id)?;
+    write_string(out, side.language)?;
+    write!(out, ", \"text\": ")?;
+    write_string(out, side.text)?;
+    write!(out, ", \"tokens\": [")?;
+    for (i, token) in side.tokens.iter().enumerate() {
+        if i > 0 {
+            write!(out, ", ")?;
+        }
+        write!(out, "{}", token)?;
+    }
+    write!(out, "]}}")
+}
+
+pub fn write_pair<W: Write>(out: &mut W, split: &str, prim: &Side, other: &Side) -> Result<()> {
+    write!(out, "{{\"split\": ")?;
+    write_string(out, split)?;
+    write!(out, ", \"prim\": ")?;
+    write_side(out, prim)?;
+    write!(out, ", \"other\": ")?;
+    write_side(out, other)?;
+    writeln!(out, "}}")
+}
@@ -0,0 +1,115 @@
+// Finds sentences which are tagged with the wrong language on Tatoeba.
+// Each language gets a profile of character trigram frequencies, built from the corpus itself. A sentence is a
+// suspect if it is more likely under another language's profile than under its own. When scoring a sentence
+// against its own language, the sentence itself is left out of the profile, so that it can't vouch for itself.
+
+use std::collections::HashMap;
+
+// Shorter sentences don't contain enough trigrams to say anything
+const MIN_TRIGRAMS: usize = 8;
+
+type Trigram = [char; 3];
+
+fn trigrams(sent: &str) -> HashMap<Trigram, u32> {
+    let chars: Vec<char> =
+        Some(' ').into_iter()
+        .chain(sent.chars().flat_map(char::to_lowercase))
+        .chain(Some(' '))
+        .collect();
+
+    let mut counts = HashMap::new();
+    for window in chars.windows(3) {
+        *counts.entry([window[0], window[1], window[2]]).or_insert(0) += 1;
+    }
+    counts
+}
+
+struct Profile {
+    counts: HashMap<Trigram, u32>,
+    total: u32,
+}
+
+impl Profile {
+    fn from_sentences<'a, I: IntoIterator<Item=&'a String>>(sents: I) -> Profile {
+        let mut counts = HashMap::new();
+        let mut total = 0;
+        for sent in sents {
+            for (trigram, count) in trigrams(sent) {
+                *counts.entry(trigram).or_insert(0) += count;
+                total += count;
+            }
+        }
+
+        Profile { counts, total }
+    }
+
+    // Average log-probability per trigram, with add-one smoothing over a vocabulary of n_trigrams
+    fn log_prob(&self, sent_trigrams: &HashMap<Trigram, u32>, n_trigrams: usize, leave_out: bool) -> f64 {
+        let sent_total: u32 = sent_trigrams.values().sum();
+        let total = if leave_out { self.total - sent_total } else { self.total };
+
+        let mut log_prob = 0.0;
+        for (trigram, &count) in sent_trigrams {
+            let mut in_profile = *self.counts.get(trigram).unwrap_or(&0);
+            if leave_out {
+                in_profile -= count;
+            }
+            let p = (in_profile as f64 + 1.0) / (total as f64 + n_trigrams as f64);
+            log_prob += count as f64 * p.ln();
+        }
+
+        log_prob / sent_total as f64
+    }
+}
+
+#[derive(Debug)]
+pub struct Suspect {
+    pub id: u32,
+    // Indices into the list of languages
+    pub label: usize,
+    pub guess: usize,
+    // How much more likely the guess is, in nats per trigram
+    pub margin: f64,
+}
+
+// Returns all sentences which are at least margin nats per trigram more likely to be in another language
+pub fn find_suspects(languages: &[&HashMap<u32, String>], margin: f64) -> Vec<Suspect> {
+    let profiles: Vec<Profile> = languages.iter().map(|sents| Profile::from_sentences(sents.values())).collect();
+
+    let mut all_trigrams = std::collections::HashSet::new();
+    for profile in &profiles {
+        all_trigrams.extend(profile.counts.keys().cloned());
+    }
+    let n_trigrams = all_trigrams.len();
+
+    let mut suspects = Vec::new();
+    for (label, sents) in languages.iter().enumerate() {
+        for (&id, sent) in sents.iter() {
+            let sent_trigrams = trigrams(sent);
+            if sent_trigrams.values().sum::<u32>() < MIN_TRIGRAMS as u32 {
+                continue;
+            }
+
+            let scores: Vec<f64> =
+                profiles
+                .iter()
+                .enumerate()
+                .map(|(i, profile)| profile.log_prob(&sent_trigrams, n_trigrams, i == label))
+                .collect();
+
+            let (guess, &best) =
+                scores
+                .iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
+                .unwrap(); // There is always at least the sentence's own language
+
+            if guess != label && best - scores[label] >= margin {
+                suspects.push(Suspect { id, label, guess, margin: best - scores[label] });
+            }
+        }
+    }
+
+    suspects.sort_by_key(|suspect| suspect.id);
+    suspects
+}
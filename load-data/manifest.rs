@@ -0,0 +1,113 @@
+// The manifest describes a build: every output file with its size and checksum, the input files it was built from,
+// the configuration and some counts. `select-langs verify` uses it to check a build directory.
+
+// One entry per line, with tab-separated fields:
+//   file    <name>  <size>  <crc32>    an output file in cache/
+//   input   <name>  <size>  <crc32>    an input file in cache/
+//   config  <name>  <value>            a configuration constant
+//   info    <name>  <value>            a count or other fact about the build, like in build.tsv
+// Checksums are CRC-32s in hexadecimal, the same as Python's zlib.crc32.
+
+use std::io::{Read, Write, Result, Error, ErrorKind, BufReader, BufRead};
+use std::fs::File;
+
+use crate::hash::Crc32;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileEntry {
+    pub name: String,
+    pub size: u64,
+    pub checksum: u32,
+}
+
+#[derive(Debug, Default)]
+pub struct Manifest {
+    pub files: Vec<FileEntry>,
+    pub inputs: Vec<FileEntry>,
+    pub config: Vec<(String, String)>,
+    pub info: Vec<(String, String)>,
+}
+
+// The size and CRC-32 of a file
+pub fn checksum_file(path: &str) -> Result<(u64, u32)> {
+    let mut file = BufReader::new(File::open(path)?);
+    let mut crc = Crc32::new();
+    let mut size = 0;
+
+    let mut buf = vec![0u8; 1 << 16];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        crc.write(&buf[..n]);
+        size += n as u64;
+    }
+
+    Ok((size, crc.finish()))
+}
+
+impl FileEntry {
+    pub fn from_file(name: &str, path: &str) -> Result<FileEntry> {
+        let (size, checksum) = checksum_file(path)?;
+        Ok(FileEntry { name: name.to_string(), size, checksum })
+    }
+}
+
+impl Manifest {
+    // Recomputes the entry of a file which has been rewritten since the manifest was written, if it is listed
+    pub fn refresh(&mut self, name: &str, path: &str) -> Result<()> {
+        for entry in self.files.iter_mut().filter(|entry| entry.name == name) {
+            *entry = FileEntry::from_file(name, path)?;
+        }
+        Ok(())
+    }
+
+    pub fn write<F: Write>(&self, out: &mut F) -> Result<()> {
+        for (kind, entries) in &[("file", &self.files), ("input", &self.inputs)] {
+            for entry in entries.iter() {
+                writeln!(out, "{}\t{}\t{}\t{:08x}", kind, entry.name, entry.size, entry.checksum)?;
+            }
+        }
+        for (kind, values) in &[("config", &self.config), ("info", &self.info)] {
+            for (name, value) in values.iter() {
+                writeln!(out, "{}\t{}\t{}", kind, name, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read(path: &str) -> Result<Manifest> {
+        let mut manifest = Manifest::default();
+
+        for (line_nr, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+
+            let invalid = || Error::new(ErrorKind::InvalidData, format!("{}:{}: invalid manifest entry", path, line_nr + 1));
+
+            let fields: Vec<&str> = line.split('\t').collect();
+            match (fields[0], fields.len()) {
+                ("file", 4) | ("input", 4) => {
+                    let entry = FileEntry {
+                        name: fields[1].to_string(),
+                        size: fields[2].parse().map_err(|_| invalid())?,
+                        checksum: u32::from_str_radix(fields[3], 16).map_err(|_| invalid())?,
+                    };
+                    if fields[0] == "file" {
+                        manifest.files.push(entry);
+                    } else {
+                        manifest.inputs.push(entry);
+                    }
+                }
+                ("config", 3) => manifest.config.push((fields[1].to_string(), fields[2].to_string())),
+                ("info", 3) => manifest.info.push((fields[1].to_string(), fields[2].to_string())),
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(manifest)
+    }
+}
@@ -0,0 +1,76 @@
+// Writes arrays in NumPy's .npy format, so that Python can memory-map them with np.load.
+// See https://numpy.org/doc/stable/reference/generated/numpy.lib.format.html
+
+// Format version 1.0:
+// - the magic string "\x93NUMPY", followed by the version bytes 1 and 0
+// - u16, the length of the header
+// - the header, a Python dict literal giving the element type, the memory order and the shape, padded with spaces
+//   and ending in a newline so that the data starts at a multiple of 64 bytes
+// - the elements, in C order
+
+use std::io::{Write, Result};
+
+const MAGIC: &[u8; 6] = b"\x93NUMPY";
+const ALIGNMENT: usize = 64;
+
+pub trait Element: Copy {
+    // The NumPy type string, like "<u4" for little-endian u32
+    const DESCR: &'static str;
+
+    fn write_le<W: Write>(self, out: &mut W) -> Result<()>;
+}
+
+impl Element for u16 {
+    const DESCR: &'static str = "<u2";
+
+    fn write_le<W: Write>(self, out: &mut W) -> Result<()> {
+        out.write_all(&self.to_le_bytes())
+    }
+}
+
+impl Element for u32 {
+    const DESCR: &'static str = "<u4";
+
+    fn write_le<W: Write>(self, out: &mut W) -> Result<()> {
+        out.write_all(&self.to_le_bytes())
+    }
+}
+
+impl Element for u64 {
+    const DESCR: &'static str = "<u8";
+
+    fn write_le<W: Write>(self, out: &mut W) -> Result<()> {
+        out.write_all(&self.to_le_bytes())
+    }
+}
+
+fn write_header<W: Write>(out: &mut W, descr: &str, shape: &[usize]) -> Result<()> {
+    // One-element tuples need a trailing comma in Python
+    let shape = match shape {
+        [n] => format!("({},)", n),
+        _ => format!("({})", shape.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(", ")),
+    };
+    let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}", descr, shape);
+
+    let unpadded = MAGIC.len() + 2 + 2 + header.len() + 1;
+    let padding = (ALIGNMENT - unpadded % ALIGNMENT) % ALIGNMENT;
+    header.push_str(&" ".repeat(padding));
+    header.push('\n');
+
+    out.write_all(MAGIC)?;
+    out.write_all(&[1, 0])?;
+    out.write_all(&(header.len() as u16).to_le_bytes())?;
+    out.write_all(header.as_bytes())?;
+    Ok(())
+}
+
+// Writes data as an array of the given shape, whose dimensions have to multiply to data.len()
+pub fn write_npy<W: Write, T: Element>(out: &mut W, shape: &[usize], data: &[T]) -> Result<()> {
+    assert_eq!(shape.iter().product::<usize>(), data.len());
+
+    write_header(out, T::DESCR, shape)?;
+    for &x in data {
+        x.write_le(out)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,97 @@
+// Numbers are written with digits in English and Spanish, but are either spelled out ("tu tu") or left as
+// untranslated digits in toki pona. Every distinct digit sequence would otherwise end up in the gram tables,
+// so this module masks or normalizes numerals before the sentences are gramified.
+
+// A number is a run of digits, optionally with single '.' or ',' separators between digits ("1,000.5").
+// Spelled out numbers are not touched, since "tu" or "wan" are just as often not numbers at all.
+
+#[allow(unused)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum NumberMode {
+    // Leave numerals as they are
+    Keep,
+    // Replace every digit with 0, so that all numbers of the same shape share grams
+    Normalize,
+    // Replace every number with a reserved number token
+    Mask,
+}
+
+// Number tokens are taken from the Unicode private use area, so they can never collide with real text.
+// The n:th distinct number of a sentence is replaced by token n, which lets a translation refer back to
+// the same number. Once a sentence has used all tokens, its further numbers are left as they are, so that every
+// number token stands for exactly one number.
+pub const N_NUMBER_TOKENS: usize = 8;
+const NUMBER_TOKEN_BASE: u32 = 0xE000;
+
+pub fn number_token(slot: usize) -> char {
+    std::char::from_u32(NUMBER_TOKEN_BASE + slot as u32).unwrap() // Always in the private use area
+}
+
+// The tokens which have to be reserved in the gram table for a given mode
+pub fn reserved_tokens(mode: NumberMode) -> Vec<char> {
+    match mode {
+        NumberMode::Mask => (0..N_NUMBER_TOKENS).map(number_token).collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MaskedNumber {
+    pub slot: usize,
+    pub original: String,
+}
+
+fn is_separator(ch: char) -> bool {
+    ch == '.' || ch == ','
+}
+
+// Returns the rewritten sentence, along with the numbers that were replaced.
+pub fn mask_numbers(sentence: &str, mode: NumberMode) -> (String, Vec<MaskedNumber>) {
+    if mode == NumberMode::Keep {
+        return (sentence.to_string(), Vec::new());
+    }
+
+    let chars: Vec<char> = sentence.chars().collect();
+    let mut out = String::with_capacity(sentence.len());
+    let mut numbers: Vec<MaskedNumber> = Vec::new();
+
+    let mut at = 0;
+    while at < chars.len() {
+        if !chars[at].is_numeric() {
+            out.push(chars[at]);
+            at += 1;
+            continue;
+        }
+
+        let start = at;
+        while at < chars.len() {
+            if chars[at].is_numeric() {
+                at += 1;
+            } else if is_separator(chars[at]) && chars.get(at + 1).is_some_and(|ch| ch.is_numeric()) {
+                at += 2;
+            } else {
+                break;
+            }
+        }
+        let original: String = chars[start..at].iter().collect();
+
+        let slot = match numbers.iter().find(|num| num.original == original) {
+            Some(num) => Some(num.slot),
+            None if mode == NumberMode::Mask && numbers.len() == N_NUMBER_TOKENS => None,
+            None => {
+                let slot = numbers.len();
+                numbers.push(MaskedNumber { slot, original: original.clone() });
+                Some(slot)
+            }
+        };
+
+        match (mode, slot) {
+            (NumberMode::Mask, Some(slot)) => out.push(number_token(slot)),
+            (NumberMode::Mask, None) => out.push_str(&original), // Out of number tokens
+            (NumberMode::Normalize, _) => out.extend(original.chars().map(|ch| if ch.is_numeric() { '0' } else { ch })),
+            (NumberMode::Keep, _) => unreachable!(), // Returned early above
+        }
+    }
+
+    (out, numbers)
+}
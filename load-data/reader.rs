@@ -0,0 +1,391 @@
+// Reads a build, so that Rust tools can use it without knowing the file formats. The sentence and links files are
+// memory-mapped, so opening a build is cheap and only the pairs which are used get read.
+//
+//     let build = Build::open("cache")?;
+//     for pair in build.pairs(Task::Sec, Split::Train).iter() {
+//         let pair = pair?;
+//         println!("{} -> {}", pair.prim.text()?, pair.other.text()?);
+//     }
+
+use std::collections::HashMap;
+use std::io::{Result, Error, ErrorKind};
+use std::ops::Deref;
+
+use crate::format::{self, Header, Kind, HEADER_SIZE};
+use crate::rng::Rng;
+use crate::splits::Split;
+use crate::tokens::{self, Gram};
+use crate::varint;
+
+#[cfg(all(unix, target_pointer_width = "64"))]
+mod sys {
+    use std::os::raw::{c_int, c_void};
+
+    pub const PROT_READ: c_int = 1;
+    pub const MAP_PRIVATE: c_int = 2;
+
+    extern "C" {
+        pub fn mmap(addr: *mut c_void, len: usize, prot: c_int, flags: c_int, fd: c_int, offset: i64) -> *mut c_void;
+        pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+}
+
+// A read-only file in memory. Mapped on 64-bit unix, read in whole elsewhere, since mmap's offset is declared as an
+// i64 and off_t is only that wide on 64-bit targets.
+pub struct Mmap {
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    ptr: *const u8,
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    len: usize,
+    #[cfg(not(all(unix, target_pointer_width = "64")))]
+    data: Vec<u8>,
+}
+
+impl Mmap {
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    pub fn open(path: &str) -> Result<Mmap> {
+        use std::fs::File;
+        use std::os::unix::io::AsRawFd;
+
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            // Empty mappings aren't allowed
+            return Ok(Mmap { ptr: std::ptr::NonNull::dangling().as_ptr(), len: 0 });
+        }
+
+        let ptr = unsafe { sys::mmap(std::ptr::null_mut(), len, sys::PROT_READ, sys::MAP_PRIVATE, file.as_raw_fd(), 0) };
+        if ptr as isize == -1 {
+            return Err(Error::last_os_error());
+        }
+        // The mapping stays valid after the file is closed
+        Ok(Mmap { ptr: ptr as *const u8, len })
+    }
+
+    #[cfg(not(all(unix, target_pointer_width = "64")))]
+    pub fn open(path: &str) -> Result<Mmap> {
+        Ok(Mmap { data: std::fs::read(path)? })
+    }
+}
+
+impl Deref for Mmap {
+    type Target = [u8];
+
+    #[cfg(all(unix, target_pointer_width = "64"))]
+    fn deref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr, self.len) }
+    }
+
+    #[cfg(not(all(unix, target_pointer_width = "64")))]
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+#[cfg(all(unix, target_pointer_width = "64"))]
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe { sys::munmap(self.ptr as *mut _, self.len) };
+        }
+    }
+}
+
+// A mapped file written by select-langs, with its header checked
+pub struct Artifact {
+    pub header: Header,
+    map: Mmap,
+}
+
+impl Artifact {
+    pub fn open(path: &str, kinds: &[Kind]) -> Result<Artifact> {
+        let with_path = |e: Error| Error::new(e.kind(), format!("{}: {}", path, e));
+
+        let map = Mmap::open(path).map_err(with_path)?;
+        let header = Header::read(&mut &map[..]).map_err(with_path)?;
+        if !kinds.contains(&header.kind) {
+            return Err(with_path(Error::new(ErrorKind::InvalidData, format!("expected {:?}, found {:?}", kinds, header.kind))));
+        }
+        if header.file_size() != map.len() as u64 {
+            return Err(with_path(Error::new(
+                ErrorKind::InvalidData,
+                format!("header describes {} bytes, but the file has {}", header.file_size(), map.len()),
+            )));
+        }
+
+        Ok(Artifact { header, map })
+    }
+
+    // Everything after the header
+    pub fn data(&self) -> &[u8] {
+        &self.map[HEADER_SIZE as usize..]
+    }
+
+    // The i-th number of the file's width
+    pub fn number(&self, i: usize) -> u64 {
+        read_number(self.data(), self.header.width, i)
+    }
+}
+
+fn read_number(bytes: &[u8], width: u8, i: usize) -> u64 {
+    let width = width as usize;
+    let mut buf = [0u8; 8];
+    buf[..width].copy_from_slice(&bytes[i * width..(i + 1) * width]);
+    u64::from_le_bytes(buf)
+}
+
+// A sentence file, with fixed width tokens or varints
+pub struct Stream {
+    pub file: Artifact,
+    // Only for varints, see varint.rs
+    pub grams_by_rank: Option<Vec<usize>>,
+}
+
+impl Stream {
+    // The ranks are only read if the sentence file consists of varints
+    pub fn open(sentences_path: &str, ranks_path: &str) -> Result<Stream> {
+        let file = Artifact::open(sentences_path, &[Kind::Sentences, Kind::Varints])?;
+
+        let grams_by_rank = if file.header.kind == Kind::Varints {
+            let ranks = Artifact::open(ranks_path, &[Kind::Ranks])?;
+            format::check_same_build(&[(sentences_path, &file.header), (ranks_path, &ranks.header)])?;
+            Some((0..ranks.header.count as usize).map(|rank| ranks.number(rank) as usize).collect())
+        } else {
+            None
+        };
+
+        Ok(Stream { file, grams_by_rank })
+    }
+
+    // Decodes the tokens in bytes, which have to be part of this stream
+    pub fn decode(&self, bytes: &[u8]) -> Result<Vec<usize>> {
+        match self.grams_by_rank {
+            Some(ref grams_by_rank) => varint::decode(bytes, grams_by_rank),
+            None => {
+                let width = self.file.header.width;
+                Ok((0..bytes.len() / width as usize).map(|i| read_number(bytes, width, i) as usize).collect())
+            }
+        }
+    }
+
+    pub fn all_tokens(&self) -> Result<Vec<usize>> {
+        self.decode(self.file.data())
+    }
+}
+
+// Everything needed to decode the sentences of a language
+pub struct Language {
+    pub code: String,
+    pub grams: Vec<Gram<char>>,
+    pub stream: Stream,
+    // The Tatoeba ID of each sentence, sorted, and where each sentence starts in the stream, followed by the end
+    ids: Artifact,
+    index: Artifact,
+}
+
+impl Language {
+    // name is prim, sec or aux
+    pub fn open(dir: &str, name: &str) -> Result<Language> {
+        let path = |kind: &str| format!("{}/{}-{}.bin", dir, kind, name);
+
+        let (grams_header, mut grams_reader) = format::open(&path("ngrams"), Kind::Grams)?;
+        let grams = tokens::decode_grams(&mut grams_reader, grams_header.count as usize)?;
+        let stream = Stream::open(&path("sentences"), &path("ranks"))?;
+        let ids = Artifact::open(&path("ids"), &[Kind::Ids])?;
+        let index = Artifact::open(&path("index"), &[Kind::Index])?;
+
+        format::check_same_build(&[
+            (&path("ngrams"), &grams_header),
+            (&path("sentences"), &stream.file.header),
+            (&path("ids"), &ids.header),
+            (&path("index"), &index.header),
+        ])?;
+        if index.header.count != ids.header.count + 1 {
+            return Err(Error::new(ErrorKind::InvalidData, format!("{} and {} don't have the same sentences", path("ids"), path("index"))));
+        }
+
+        Ok(Language { code: stream.file.header.language.clone(), grams, stream, ids, index })
+    }
+
+    pub fn n_sentences(&self) -> usize {
+        self.ids.header.count as usize
+    }
+
+    // The sentence starting at a byte offset, as found in the links files
+    pub fn sentence_at(&self, offset: u64, len: u64) -> Result<Sentence<'_>> {
+        let data = self.stream.file.data();
+        if offset.checked_add(len).is_none_or(|end| end > data.len() as u64) {
+            return Err(Error::new(ErrorKind::InvalidData, format!("sentence at {}+{} is outside of the {} sentence file", offset, len, self.code)));
+        }
+
+        // The index is sorted, so the sentence can be found by its offset
+        let (mut lo, mut hi) = (0, self.n_sentences());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if self.index.number(mid) < offset { lo = mid + 1 } else { hi = mid }
+        }
+        let id = if lo < self.n_sentences() && self.index.number(lo) == offset { Some(self.ids.number(lo) as u32) } else { None };
+
+        Ok(Sentence { language: self, id, offset, bytes: &data[offset as usize..(offset + len) as usize] })
+    }
+
+    // The sentence with a Tatoeba ID, if it is in the build
+    pub fn sentence_by_id(&self, id: u32) -> Result<Option<Sentence<'_>>> {
+        let (mut lo, mut hi) = (0, self.n_sentences());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if self.ids.number(mid) < id as u64 { lo = mid + 1 } else { hi = mid }
+        }
+        if lo == self.n_sentences() || self.ids.number(lo) != id as u64 {
+            return Ok(None);
+        }
+
+        let (start, end) = (self.index.number(lo), self.index.number(lo + 1));
+        self.sentence_at(start, end - start).map(Some)
+    }
+}
+
+pub struct Sentence<'a> {
+    pub language: &'a Language,
+    pub id: Option<u32>,
+    // Where the sentence starts in its sentence file, after the header
+    pub offset: u64,
+    // The encoded tokens, straight from the sentence file
+    pub bytes: &'a [u8],
+}
+
+impl<'a> Sentence<'a> {
+    pub fn tokens(&self) -> Result<Vec<usize>> {
+        self.language.stream.decode(self.bytes)
+    }
+
+    // The text of each token
+    pub fn pieces(&self) -> Result<Vec<String>> {
+        Ok(
+            self.tokens()?
+                .into_iter()
+                .map(|token| tokens::decompose_sequence(vec![token], &self.language.grams).into_iter().collect())
+                .collect()
+        )
+    }
+
+    #[allow(unused)]
+    pub fn text(&self) -> Result<String> {
+        Ok(self.pieces()?.concat())
+    }
+}
+
+pub struct Pair<'a> {
+    // The index of the link in its links file
+    pub index: usize,
+    pub prim: Sentence<'a>,
+    pub other: Sentence<'a>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Task {
+    Sec,
+    Aux,
+}
+
+impl Task {
+    pub const ALL: [Task; 2] = [Task::Sec, Task::Aux];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Task::Sec => "sec",
+            Task::Aux => "aux",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Task> {
+        Task::ALL.iter().cloned().find(|task| task.name() == name)
+    }
+}
+
+// All three languages of a build, and the links files of both tasks for every split
+pub struct Build {
+    pub prim: Language,
+    pub sec: Language,
+    pub aux: Language,
+    links: HashMap<(Task, Split), Artifact>,
+}
+
+impl Build {
+    pub fn open(dir: &str) -> Result<Build> {
+        let prim = Language::open(dir, "prim")?;
+        let sec = Language::open(dir, "sec")?;
+        let aux = Language::open(dir, "aux")?;
+
+        let sentences_path = |name: &str| format!("{}/sentences-{}.bin", dir, name);
+        let prim_path = sentences_path("prim");
+        format::check_same_build(&[
+            (&prim_path, &prim.stream.file.header),
+            (&sentences_path("sec"), &sec.stream.file.header),
+            (&sentences_path("aux"), &aux.stream.file.header),
+        ])?;
+
+        let mut links = HashMap::new();
+        for &task in &Task::ALL {
+            for &split in &Split::ALL {
+                let path = format!("{}/{}-links-{}.bin", dir, task.name(), split.name());
+                let file = Artifact::open(&path, &[Kind::Links])?;
+                format::check_same_build(&[(&prim_path, &prim.stream.file.header), (&path, &file.header)])?;
+                links.insert((task, split), file);
+            }
+        }
+
+        Ok(Build { prim, sec, aux, links })
+    }
+
+    pub fn other(&self, task: Task) -> &Language {
+        match task {
+            Task::Sec => &self.sec,
+            Task::Aux => &self.aux,
+        }
+    }
+
+    pub fn pairs(&self, task: Task, split: Split) -> Pairs<'_> {
+        Pairs { prim: &self.prim, other: self.other(task), links: &self.links[&(task, split)] }
+    }
+}
+
+// The pairs of one links file, in the order of the file
+pub struct Pairs<'a> {
+    prim: &'a Language,
+    other: &'a Language,
+    links: &'a Artifact,
+}
+
+impl<'a> Pairs<'a> {
+    pub fn len(&self) -> usize {
+        self.links.header.count as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn pair(&self, i: usize) -> Result<Pair<'a>> {
+        if i >= self.len() {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("link {} is out of range, there are {} links", i, self.len())));
+        }
+        let number = |j| self.links.number(i * 4 + j);
+        Ok(Pair {
+            index: i,
+            prim: self.prim.sentence_at(number(0), number(1))?,
+            other: self.other.sentence_at(number(2), number(3))?,
+        })
+    }
+
+    pub fn random_pair(&self, rng: &mut Rng) -> Result<Pair<'a>> {
+        if self.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidInput, "there are no links"));
+        }
+        self.pair(rng.below(self.len()))
+    }
+
+    pub fn iter<'b>(&'b self) -> impl Iterator<Item=Result<Pair<'a>>> + 'b {
+        (0..self.len()).map(move |i| self.pair(i))
+    }
+}
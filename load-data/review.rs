@@ -0,0 +1,151 @@
+// Draws samples of pairs for our toki pona speakers to check by hand.
+// The sample is stratified by length, by who added the translation, and by whether the pair passed the filters,
+// so that every kind of pair gets checked, not just the most common ones.
+
+// The review file lists each pair as comments, followed by a verdict line:
+//   # length 20-49, contributor someone, passed
+//   # eng 1234: I like cats.
+//   # toki 5678: mi olin e soweli.
+//   ?	1234	5678
+// Reviewers replace the ? with good or bad. `select-langs apply-review` then adds the bad pairs to the blocklist.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{Write, Result, Error, ErrorKind, BufReader, BufRead};
+use std::fs::File;
+
+use crate::rng::Rng;
+
+// Boundaries between the length buckets, in characters of the longest sentence of the pair
+const LENGTH_BOUNDARIES: &[usize] = &[20, 50, 100];
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Stratum {
+    pub length_bucket: usize,
+    pub contributor: String,
+    // The filter which dropped the pair, if any
+    pub filter: Option<&'static str>,
+}
+
+impl Stratum {
+    fn describe(&self) -> String {
+        let lower = if self.length_bucket == 0 { 0 } else { LENGTH_BOUNDARIES[self.length_bucket - 1] };
+        let length = match LENGTH_BOUNDARIES.get(self.length_bucket) {
+            Some(upper) => format!("{}-{}", lower, upper - 1),
+            None => format!("{}+", lower),
+        };
+        let filter = match self.filter {
+            Some(filter) => format!("dropped by {} filter", filter),
+            None => "passed".to_string(),
+        };
+        format!("length {}, contributor {}, {}", length, self.contributor, filter)
+    }
+}
+
+pub fn length_bucket(len: usize) -> usize {
+    LENGTH_BOUNDARIES.iter().take_while(|&&boundary| len >= boundary).count()
+}
+
+pub struct ReviewPair {
+    pub prim_id: u32,
+    pub other_id: u32,
+    pub prim_text: String,
+    pub other_text: String,
+    pub stratum: Stratum,
+}
+
+// Reads the usernames of the given sentences from Tatoeba's sentences_detailed export, where each line is
+// ID, language, text, username, date added, date modified. A missing file gives no usernames.
+pub fn read_contributors(path: &str, ids: &HashSet<u32>) -> Result<HashMap<u32, String>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut contributors = HashMap::new();
+    for line in BufReader::new(file).split(b'\n') {
+        let line = line?;
+        let mut fields = line.split(|&b| b == b'\t');
+
+        let id = match fields.next().and_then(|id| std::str::from_utf8(id).ok()).and_then(|id| id.parse().ok()) {
+            Some(id) => id,
+            None => continue,
+        };
+        if !ids.contains(&id) {
+            continue;
+        }
+
+        // Sentences without an owner have the username \N
+        match fields.nth(2) {
+            Some(username) if username != b"\\N" => { contributors.insert(id, String::from_utf8_lossy(username).into_owned()); }
+            _ => {}
+        }
+    }
+
+    Ok(contributors)
+}
+
+// Draws up to per_stratum pairs from every stratum, sorted by stratum
+pub fn stratified_sample(pairs: Vec<ReviewPair>, per_stratum: usize, rng: &mut Rng) -> Vec<ReviewPair> {
+    let mut strata: HashMap<Stratum, Vec<ReviewPair>> = HashMap::new();
+    for pair in pairs {
+        strata.entry(pair.stratum.clone()).or_default().push(pair);
+    }
+
+    let mut strata: Vec<(Stratum, Vec<ReviewPair>)> = strata.into_iter().collect();
+    strata.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut sample = Vec::new();
+    for (_, mut pairs) in strata {
+        // Sorted first, so that the sample only depends on the seed
+        pairs.sort_by_key(|pair| (pair.prim_id, pair.other_id));
+        rng.shuffle(&mut pairs);
+        pairs.truncate(per_stratum);
+        sample.extend(pairs);
+    }
+    sample
+}
+
+pub fn write_review<F: Write>(file: &mut F, pairs: &[ReviewPair], prim_language: &str, other_language: &str) -> Result<()> {
+    writeln!(file, "# Replace each ? with good or bad, then run `select-langs apply-review` to block the bad pairs")?;
+    for pair in pairs {
+        writeln!(file)?;
+        writeln!(file, "# {}", pair.stratum.describe())?;
+        writeln!(file, "# {} {}: {}", prim_language, pair.prim_id, pair.prim_text)?;
+        writeln!(file, "# {} {}: {}", other_language, pair.other_id, pair.other_text)?;
+        writeln!(file, "?\t{}\t{}", pair.prim_id, pair.other_id)?;
+    }
+    Ok(())
+}
+
+// Returns the links marked as bad, and the number of pairs which haven't been reviewed yet
+pub fn read_bad_links(path: &str) -> Result<(Vec<(u32, u32)>, usize)> {
+    let mut bad = Vec::new();
+    let mut n_unreviewed = 0;
+
+    for (line_nr, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let invalid = || Error::new(ErrorKind::InvalidData, format!("{}:{}: expected verdict and two sentence IDs", path, line_nr + 1));
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 3 {
+            return Err(invalid());
+        }
+        let prim_id: u32 = fields[1].parse().map_err(|_| invalid())?;
+        let other_id: u32 = fields[2].parse().map_err(|_| invalid())?;
+
+        match fields[0] {
+            "bad" => bad.push((prim_id, other_id)),
+            "good" => {}
+            "?" => n_unreviewed += 1,
+            _ => return Err(invalid()),
+        }
+    }
+
+    Ok((bad, n_unreviewed))
+}
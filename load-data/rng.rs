@@ -0,0 +1,31 @@
+// A small seedable random number generator (SplitMix64), so that builds are reproducible without any dependencies
+
+use crate::hash::mix64;
+
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        mix64(self.state)
+    }
+
+    // Uniform in [0, n), n must not be zero
+    pub fn below(&mut self, n: usize) -> usize {
+        // The bias is negligible for the sizes we deal with
+        (self.next_u64() % n as u64) as usize
+    }
+
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.below(i + 1);
+            items.swap(i, j);
+        }
+    }
+}
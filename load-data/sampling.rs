@@ -0,0 +1,101 @@
+// Helpers for writing the links in a way that makes sampling batches cheap for the trainer
+
+use std::io::{Write, Result};
+use std::collections::HashMap;
+
+use crate::hash::mix64;
+use crate::rng::Rng;
+
+// A run of links with similar lengths in a links file sorted by length.
+// The length of a link is the number of tokens in its longest sentence.
+#[derive(Debug)]
+pub struct Bucket {
+    pub min_len: usize,
+    pub max_len: usize,
+    // Index of the first link, and number of links
+    pub start: usize,
+    pub count: usize,
+}
+
+// Groups sorted lengths into buckets covering width lengths each. Empty buckets are left out.
+pub fn bucket_by_length(lengths: &[usize], width: usize) -> Vec<Bucket> {
+    let mut buckets: Vec<Bucket> = Vec::new();
+
+    for (i, &len) in lengths.iter().enumerate() {
+        match buckets.last_mut() {
+            Some(ref mut bucket) if len / width == bucket.min_len / width => {
+                debug_assert!(len >= bucket.max_len);
+                bucket.max_len = len;
+                bucket.count += 1;
+                continue;
+            }
+            _ => {}
+        }
+
+        buckets.push(Bucket { min_len: len, max_len: len, start: i, count: 1 });
+    }
+
+    buckets
+}
+
+// Each bucket is encoded as four 32-bit unsigned integers: min length, max length, first link, number of links
+pub fn write_buckets<F: Write>(file: &mut F, buckets: &[Bucket]) -> Result<()> {
+    for bucket in buckets {
+        for &number in &[bucket.min_len, bucket.max_len, bucket.start, bucket.count] {
+            file.write_all(&(number as u32).to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+// The order in which to visit the links during one epoch. The permutation of epoch e is a Fisher-Yates shuffle
+// of 0..n_links using Rng::new(mix64(seed ^ e)), so the trainer can also regenerate it for any epoch.
+pub fn epoch_permutation(n_links: usize, seed: u64, epoch: usize) -> Vec<u32> {
+    let mut permutation: Vec<u32> = (0..n_links as u32).collect();
+    Rng::new(mix64(seed ^ epoch as u64)).shuffle(&mut permutation);
+    permutation
+}
+
+// The permutations of n_epochs epochs back to back, each link index encoded as a 32-bit unsigned integer
+pub fn write_permutations<F: Write>(file: &mut F, n_links: usize, seed: u64, n_epochs: usize) -> Result<()> {
+    for epoch in 0..n_epochs {
+        for idx in epoch_permutation(n_links, seed, epoch) {
+            file.write_all(&idx.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+// How often to sample from each task (secondary or auxiliary), proportional to its size to the power of 1/temperature.
+// A temperature of 1 samples every pair equally often, higher temperatures upsample the smaller tasks.
+pub fn task_probabilities(sizes: &[usize], temperature: f64) -> Vec<f64> {
+    let scaled: Vec<f64> = sizes.iter().map(|&size| (size as f64).powf(1.0 / temperature)).collect();
+    let total: f64 = scaled.iter().sum();
+    scaled.into_iter().map(|x| x / total).collect()
+}
+
+// Weights of the links within a task, summing to 1. With balance_prim, every primary sentence gets the same
+// total weight, no matter how many translations it has, otherwise every link gets the same weight.
+pub fn pair_weights(links: &[(u32, u32)], balance_prim: bool) -> Vec<f64> {
+    let mut n_translations: HashMap<u32, usize> = HashMap::new();
+    for &(prim_id, _) in links {
+        *n_translations.entry(prim_id).or_insert(0) += 1;
+    }
+
+    let weights: Vec<f64> =
+        links
+        .iter()
+        .map(|&(prim_id, _)| if balance_prim { 1.0 / n_translations[&prim_id] as f64 } else { 1.0 })
+        .collect();
+
+    let total: f64 = weights.iter().sum();
+    weights.into_iter().map(|w| w / total).collect()
+}
+
+// Each weight is encoded as a 32-bit little-endian float
+pub fn write_weights<F: Write>(file: &mut F, weights: &[f64]) -> Result<()> {
+    for &weight in weights {
+        file.write_all(&(weight as f32).to_le_bytes())?;
+    }
+    Ok(())
+}
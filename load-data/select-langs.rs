@@ -1,54 +1,193 @@
 // This program removes all sentences which does not either have primary-secondary pair, or primary-auxiliary pair
-// It then separates each language's sentences into separate files, and creates a two links files with information about
-// where each sentence starts and ends in each language.
+// It then separates each language's sentences into separate files, and creates links files with information about
+// where each sentence starts and ends in each language. There are two links files (secondary and auxiliary) for each
+// of the train, dev and test splits. The links in each file are sorted by length, see sampling.rs.
 
-// The generated sentence files contains every sentence back to back, with no separators.
+// The generated sentence files contains every sentence back to back, with no separators. Each token is a 16-bit
+// unsigned integer, or a 32-bit one if a gram table has more than 65536 grams; the header gives the width.
 // The links files contains links between the sentences. Each sentence link is encoded as
 // four numbers. The four numbers describe the start of the primary sentence, the end of it, the start of the
 // secondary/auxiliary sentence and the end of it.
-// In the binary format, each number is encoded as a 32-bit unsigned integer
+// In the binary format, each number is encoded as a 32-bit unsigned integer, or as a 64-bit one if any sentence
+// file is too large for 32-bit offsets. The width of the numbers is given in the header.
+// Every binary file starts with a header describing its contents, see format.rs. Offsets in the links files
+// are counted from the end of the sentence file's header.
 
 mod tokens;
+mod numbers;
+mod filters;
+mod langid;
+mod hash;
+mod rng;
+mod splits;
+mod sampling;
+mod curriculum;
+mod review;
+mod format;
+mod container;
+mod npy;
+mod jsonl;
+mod arrow;
+mod manifest;
+mod verify;
+mod inspect;
+mod varint;
+mod reader;
 
 use std::io::{Write, Result, BufWriter, BufReader, BufRead, Error, ErrorKind};
-use std::fs::File;
+use std::fs::{File, OpenOptions};
 use std::env::var;
 use std::collections::{HashMap, HashSet};
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
+use std::str::FromStr;
+
+use numbers::NumberMode;
+use splits::Split;
+use format::{Header, Kind};
+use filters::{Script, CharSet, CharPolicy, CharReport, LengthLimit, IdList, IdFilter};
 
 const PRIM_LANGUAGE: &str = "eng";
 const SEC_LANGUAGE: &str = "toki";
 const AUX_LANGUAGE: &str = "spa";
 
 const REL_LIM: f64 = 0.0001;
+// The most grams each language may have. Tokens are written as 16-bit numbers if all gram tables fit, otherwise as 32-bit ones.
+const MAX_GRAMS: usize = 1 << 16;
+
+// Files in the cache directory with Tatoeba sentence IDs and links to leave out, or to exclusively include
+// See filters::IdList for the format. Missing files are treated as empty lists.
+const BLOCKLIST_FILE: &str = "blocklist.tsv";
+const ALLOWLIST_FILE: &str = "allowlist.tsv";
+
+// The characters allowed in each language, and what to do with sentences containing anything else
+// Rejected characters are listed in rejected-chars.tsv
+const PRIM_CHARS: CharSet = CharSet { scripts: &[Script::Common, Script::Latin], extra: "" };
+const SEC_CHARS: CharSet = CharSet { scripts: &[Script::Common, Script::Latin], extra: "" };
+const AUX_CHARS: CharSet = CharSet { scripts: &[Script::Common, Script::Latin], extra: "" };
+const CHAR_POLICY: CharPolicy = CharPolicy::Drop;
+
+// Longer sentences are dropped, None means no limit
+// A histogram of the token lengths before the limit is applied is written to lengths.tsv
+const PRIM_LIMIT: LengthLimit = LengthLimit { max_chars: Some(300), max_tokens: Some(100) };
+const SEC_LIMIT: LengthLimit = LengthLimit { max_chars: Some(300), max_tokens: Some(100) };
+const AUX_LIMIT: LengthLimit = LengthLimit { max_chars: Some(300), max_tokens: Some(100) };
+
+// Look for sentences which seem to be tagged with the wrong language, see langid.rs
+// Suspects are written to suspect-languages.tsv, and removed if DROP_SUSPECT_LANGUAGES is set
+const CHECK_LANGUAGES: bool = true;
+const DROP_SUSPECT_LANGUAGES: bool = false;
+// How much more likely (in nats per character trigram) another language has to be for a sentence to be a suspect
+const SUSPECT_MARGIN: f64 = 0.5;
+
+// Fractions of the link components (see splits.rs) which go into the dev and test splits
+// Changing the key reshuffles the splits, but sentences listed in SPLITS_FILE always keep their previous split
+const DEV_FRACTION: f64 = 0.05;
+const TEST_FRACTION: f64 = 0.05;
+const SPLIT_KEY: u64 = 0x746f6b69;
+const SPLITS_FILE: &str = "splits.tsv";
+
+// Optionally, the secondary train links are also divided into this many folds for cross-validation, grouped by
+// link component like the splits. Each link's fold is written to sec-folds.bin as one byte, in the same order as
+// sec-links-train.bin, so there can be at most 256 folds. The auxiliary links stay in training for every fold.
+const N_FOLDS: Option<usize> = None;
+const FOLD_KEY: u64 = 0x6b697465;
+
+// The links of each split are sorted by length, and grouped into buckets covering this many lengths
+// The buckets are listed in sec-buckets-{split}.bin and aux-buckets-{split}.bin, see sampling.rs
+const BUCKET_WIDTH: usize = 4;
+
+// Permutations of the train links for this many epochs are written to sec-epochs.bin and aux-epochs.bin
+// Run `select-langs permute <n_epochs>` to write permutations for more epochs without rebuilding
+const N_EPOCHS: usize = 16;
+const EPOCH_SEED: u64 = 0x6c6f6e61;
+
+// Sampling weights for the train links are written to sec-weights.bin and aux-weights.bin, summing to 1 over both files
+// The secondary and auxiliary tasks are sampled proportionally to their size to the power of 1/TASK_TEMPERATURE,
+// and with BALANCE_PRIM_SENTENCES, primary sentences with many translations aren't sampled more often.
+const TASK_TEMPERATURE: f64 = 3.0;
+const BALANCE_PRIM_SENTENCES: bool = true;
+
+// The train links sorted from easiest to hardest are written to sec-curriculum.bin and aux-curriculum.bin,
+// with their scores in sec-curriculum-scores.bin and aux-curriculum-scores.bin, see curriculum.rs
+const CURRICULUM_WEIGHTS: curriculum::Weights = curriculum::Weights { length: 1.0, rarity: 1.0, ratio: 0.5 };
+
+// `select-langs review` samples this many secondary pairs from each stratum (see review.rs) into review.txt
+// Contributors are read from raw/sentences_detailed.tsv if it has been downloaded from Tatoeba
+const REVIEW_PER_STRATUM: usize = 5;
+const REVIEW_FILE: &str = "review.txt";
+
+// With WRITE_CONTAINER, the whole build is also packed into CONTAINER_FILE, see container.rs
+// `select-langs pack` and `select-langs unpack` convert between the container and the loose files in cache/
+const WRITE_CONTAINER: bool = false;
+const CONTAINER_FILE: &str = "build.ilpt";
+// Metadata about the build, one key and value per line
+const BUILD_INFO_FILE: &str = "build.tsv";
+// Every output and input file with its checksum, the configuration and the metadata, see manifest.rs
+// `select-langs verify` checks the build in cache/ against it
+const MANIFEST_FILE: &str = "manifest.tsv";
+
+// With WRITE_NPY, the sentences and links are also written as NumPy arrays to the NPY_DIR directory in cache/:
+// tokens-{prim,sec,aux}.npy, all tokens of a language back to back, offsets-{prim,sec,aux}.npy, where each
+// sentence starts in the tokens (plus the total number of tokens at the end), and {sec,aux}-links-{split}.npy,
+// the indices of the primary and other sentence of each link, in the same order as the links files.
+const WRITE_NPY: bool = false;
+const NPY_DIR: &str = "npy";
+
+// With WRITE_JSONL, every link is also written to JSONL_FILE with the IDs, text and tokens of both sentences, see jsonl.rs
+// The text is as it was before numbers were masked.
+const WRITE_JSONL: bool = false;
+const JSONL_FILE: &str = "pairs.jsonl";
+
+// With WRITE_ARROW, the links of each language pair are also written as an Arrow IPC file to the ARROW_DIR directory
+// in cache/, {prim}-{sec}.arrow and {prim}-{aux}.arrow, for reading with pyarrow or pandas. There is one record batch
+// per split, with the columns split, prim_id, other_id, prim_text, other_text, prim_tokens and other_tokens.
+// The text is as it was before numbers were masked, like for WRITE_JSONL.
+const WRITE_ARROW: bool = false;
+const ARROW_DIR: &str = "arrow";
+
+// With VARINT_SENTENCES, the sentence files store each token as a varint of its frequency rank, which takes about half
+// the space. The gram of each rank is written to ranks-{prim,sec,aux}.bin, see varint.rs.
+const VARINT_SENTENCES: bool = false;
+
+// How numerals are treated before gramifying, see numbers.rs
+// The original values of masked numbers are written to numbers.tsv
+const NUMBER_MODE: NumberMode = NumberMode::Mask;
 
 // Described above
 // TODO: Either make this a compile-time flag, or a CLI-argument
 const BINARY_MODE: bool = true;
 
-fn write_binary_number_to_file<F: Write>(file: &mut F, number: u32) -> Result<()> {
-    let buf = number.to_le_bytes();
-    file.write(&buf)?;
+fn write_binary_number_to_file<F: Write>(file: &mut F, number: u64, width: u8) -> Result<()> {
+    match width {
+        4 => {
+            let number: u32 = number.try_into().map_err(|_| Error::new(ErrorKind::InvalidData, format!("{} is too large to fit in a u32!", number)))?;
+            file.write_all(&number.to_le_bytes())?;
+        }
+        8 => {
+            file.write_all(&number.to_le_bytes())?;
+        }
+        _ => return Err(Error::new(ErrorKind::InvalidInput, format!("numbers can't be {} bytes wide", width))),
+    }
 
     Ok(())
 }
 
-fn write_ascii_number_to_file<F: Write>(file: &mut F, number: u32) -> Result<()> {
+fn write_ascii_number_to_file<F: Write>(file: &mut F, number: u64) -> Result<()> {
     let txt = format!("{} ", number);
     file.write(txt.as_bytes())?;
 
     Ok(())
 }
 
-fn write_number_to_file<F: Write>(file: &mut F, number: u32) -> Result<()> {
+fn write_number_to_file<F: Write>(file: &mut F, number: u64, width: u8) -> Result<()> {
     if BINARY_MODE {
-        write_binary_number_to_file(file, number)
+        write_binary_number_to_file(file, number, width)
     } else {
         write_ascii_number_to_file(file, number)
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct Translation<SentenceContent> {
     prim_language: HashMap<u32, SentenceContent>,
     sec_language: HashMap<u32, SentenceContent>,
@@ -68,8 +207,10 @@ impl <SentenceContent> Translation<SentenceContent> {
 }
 
 impl Translation<Vec<u8>> {
-    fn consume_sentences<F: BufRead>(&mut self, mut file: F) -> Result<usize> {
+    // Returns the number of loaded sentences, and the number of sentences excluded by the ID lists
+    fn consume_sentences<F: BufRead>(&mut self, mut file: F, id_filter: &IdFilter) -> Result<(usize, usize)> {
         let mut counter = 0;
+        let mut n_excluded = 0;
 
         loop {
             let mut id_buf = Vec::new();
@@ -97,6 +238,11 @@ impl Translation<Vec<u8>> {
             let id_st = String::from_utf8(id_buf).unwrap();
             let id_n: u32 = id_st.parse().unwrap();
 
+            if !id_filter.allows_sentence(id_n) {
+                n_excluded += 1;
+                continue;
+            }
+
             // println!("ID: {:?}, Language: {:?}, Sentence: {:?}", id_n, language, sentence);
 
             let list_to_add =
@@ -111,12 +257,15 @@ impl Translation<Vec<u8>> {
             counter += 1;
         }
 
-        Ok(counter)
+        Ok((counter, n_excluded))
     }
 
-    fn consume_links<F: BufRead>(&mut self, mut file: F, remove_unlinked: bool) -> Result<(usize, usize)> {
+    // Returns the number of loaded links, the number of links which don't go between the right languages,
+    // and the number of links excluded by the ID lists
+    fn consume_links<F: BufRead>(&mut self, mut file: F, remove_unlinked: bool, id_filter: &IdFilter) -> Result<(usize, usize, usize)> {
         let mut n_read = 0;
         let mut n_wrong = 0;
+        let mut n_excluded = 0;
 
         let mut prim_ids = HashSet::new();
         let mut sec_ids = HashSet::new();
@@ -142,41 +291,37 @@ impl Translation<Vec<u8>> {
             let second_st = String::from_utf8(second_buf).unwrap();
             let second_n: u32 = second_st.parse().unwrap();
 
-            match (self.prim_language.contains_key(&first_n), self.sec_language.contains_key(&second_n), self.aux_language.contains_key(&second_n)) {
-                (true, true, false) => {
-                    self.links.insert((first_n, second_n));
-                    prim_ids.insert(first_n);
-                    sec_ids.insert(second_n);
-                    n_read += 1;
-                    continue;
-                }
-                (true, false, true) => {
-                    self.links.insert((first_n, second_n));
-                    prim_ids.insert(first_n);
-                    aux_ids.insert(second_n);
-                    n_read += 1;
+            // (primary ID, other ID, whether the other sentence is secondary), whichever way round the link is
+            let link = match (self.language_of(first_n), self.language_of(second_n)) {
+                (Some(0), Some(1)) => Some((first_n, second_n, true)),
+                (Some(0), Some(2)) => Some((first_n, second_n, false)),
+                (Some(1), Some(0)) => Some((second_n, first_n, true)),
+                (Some(2), Some(0)) => Some((second_n, first_n, false)),
+                _ => None,
+            };
+
+            // Only links which would otherwise be used count as excluded. Links to sentences excluded by the ID lists
+            // were already counted with the sentences, and count as wrong here since those sentences aren't loaded.
+            let (prim_id, other_id, is_sec) = match link {
+                Some(link) => link,
+                None => {
+                    n_wrong += 1;
                     continue;
                 }
-                _ => {}
+            };
+            if !id_filter.allows_link(first_n, second_n) {
+                n_excluded += 1;
+                continue;
             }
-            match (self.prim_language.contains_key(&second_n), self.sec_language.contains_key(&first_n), self.aux_language.contains_key(&first_n)) {
-                (true, true, false) => {
-                    self.links.insert((second_n, first_n));
-                    prim_ids.insert(second_n);
-                    sec_ids.insert(first_n);
-                    n_read += 1;
-                    continue;
-                }
-                (true, false, true) => {
-                    self.links.insert((second_n, first_n));
-                    prim_ids.insert(second_n);
-                    aux_ids.insert(first_n);
-                    n_read += 1;
-                    continue;
-                }
-                _ => {}
+
+            self.links.insert((prim_id, other_id));
+            prim_ids.insert(prim_id);
+            if is_sec {
+                sec_ids.insert(other_id);
+            } else {
+                aux_ids.insert(other_id);
             }
-            n_wrong += 1;
+            n_read += 1;
         }
 
         if remove_unlinked {
@@ -186,7 +331,7 @@ impl Translation<Vec<u8>> {
             self.aux_language.retain(|&id, _| aux_ids.contains(&id));
         }
 
-        Ok((n_read, n_wrong))
+        Ok((n_read, n_wrong, n_excluded))
     }
 
     fn stringify(self) -> Result<Translation<String>> {
@@ -225,30 +370,118 @@ impl Translation<Vec<u8>> {
 }
 
 impl <T> Translation<T> {
-    fn write_links<F: Write>(&self, file: &mut F, id_offset_size: &HashMap<u32, (usize, usize)>, write_secondary: bool) -> Result<()> {
-        for &(prim_id, other_id) in &self.links {
-            match (self.sec_language.contains_key(&other_id), write_secondary) {
-                (true, false) | (false, true) => continue,
-                _ => {}
-            }
+    // The sentences of one language, 0 for primary, 1 for secondary and 2 for auxiliary
+    fn language(&self, from: u8) -> &HashMap<u32, T> {
+        match from {
+            0 => &self.prim_language,
+            1 => &self.sec_language,
+            2 => &self.aux_language,
+            _ => unimplemented!(),
+        }
+    }
+
+    fn language_mut(&mut self, from: u8) -> &mut HashMap<u32, T> {
+        match from {
+            0 => &mut self.prim_language,
+            1 => &mut self.sec_language,
+            2 => &mut self.aux_language,
+            _ => unimplemented!(),
+        }
+    }
+
+    // Which language a sentence is in, 0 for primary, 1 for secondary and 2 for auxiliary
+    fn language_of(&self, id: u32) -> Option<u8> {
+        if self.prim_language.contains_key(&id) {
+            Some(0)
+        } else if self.sec_language.contains_key(&id) {
+            Some(1)
+        } else if self.aux_language.contains_key(&id) {
+            Some(2)
+        } else {
+            None
+        }
+    }
 
-            let (prim_offset, prim_len) = id_offset_size.get(&prim_id).unwrap();
-            let (other_offset, other_len) = id_offset_size.get(&other_id).unwrap();
+    // Removes links to sentences which have been filtered out, and sentences which no longer have any links.
+    // Returns the number of removed links.
+    fn remove_dangling(&mut self) -> usize {
+        let n_links = self.links.len();
 
-            write_number_to_file(file, *prim_offset as u32)?;
-            write_number_to_file(file, *prim_len as u32)?;
-            write_number_to_file(file, *other_offset as u32)?;
-            write_number_to_file(file, *other_len as u32)?;
+        let prim_language = &self.prim_language;
+        let sec_language = &self.sec_language;
+        let aux_language = &self.aux_language;
+        self.links.retain(|&(prim_id, other_id)| {
+            prim_language.contains_key(&prim_id) && (sec_language.contains_key(&other_id) || aux_language.contains_key(&other_id))
+        });
+
+        let mut linked = HashSet::new();
+        for &(prim_id, other_id) in &self.links {
+            linked.insert(prim_id);
+            linked.insert(other_id);
         }
-        Ok(())
+        self.prim_language.retain(|id, _| linked.contains(id));
+        self.sec_language.retain(|id, _| linked.contains(id));
+        self.aux_language.retain(|id, _| linked.contains(id));
+
+        n_links - self.links.len()
+    }
+
+    // The links to either the secondary or the auxiliary language within a split, sorted by ID
+    fn split_links(&self, secondary: bool, splits: &HashMap<u32, Split>, split: Split) -> Vec<(u32, u32)> {
+        let mut links: Vec<(u32, u32)> =
+            self.links
+            .iter()
+            .filter(|&&(_, other_id)| self.sec_language.contains_key(&other_id) == secondary)
+            .filter(|&&(prim_id, _)| splits[&prim_id] == split)
+            .cloned()
+            .collect();
+
+        links.sort();
+        links
+    }
+}
+
+// The width in bytes of the tokens in the sentence files: 2, unless some gram table has too many grams for 16-bit IDs
+fn token_width(n_grams: usize) -> u8 {
+    if n_grams <= u16::MAX as usize + 1 { 2 } else { 4 }
+}
+
+// The width in bytes of the numbers in the links files: 4, unless some sentence file is too large for 32-bit offsets
+fn link_width(id_offset_size: &HashMap<u32, (usize, usize)>) -> u8 {
+    let end = id_offset_size.values().map(|&(offset, len)| offset + len).max().unwrap_or(0);
+    if end <= u32::MAX as usize { 4 } else { 8 }
+}
+
+fn write_links<F: Write>(file: &mut F, links: &[(u32, u32)], id_offset_size: &HashMap<u32, (usize, usize)>, width: u8) -> Result<()> {
+    for &(prim_id, other_id) in links {
+        let (prim_offset, prim_len) = id_offset_size.get(&prim_id).unwrap();
+        let (other_offset, other_len) = id_offset_size.get(&other_id).unwrap();
+
+        write_number_to_file(file, *prim_offset as u64, width)?;
+        write_number_to_file(file, *prim_len as u64, width)?;
+        write_number_to_file(file, *other_offset as u64, width)?;
+        write_number_to_file(file, *other_len as u64, width)?;
+    }
+    Ok(())
+}
+
+fn mask_numbers_in_sentences(sents: &mut HashMap<u32, String>, mode: NumberMode, masked: &mut Vec<(u32, numbers::MaskedNumber)>) {
+    for (&id, sent) in sents.iter_mut() {
+        let (new_sent, numbers) = numbers::mask_numbers(sent, mode);
+        *sent = new_sent;
+        masked.extend(numbers.into_iter().map(|num| (id, num)));
     }
 }
 
-fn gramify_sentences(sents: HashMap<u32, String>) -> (HashMap<u32, Vec<usize>>, Gramophone) {
+fn gramify_sentences(sents: HashMap<u32, String>, reserved: &[char]) -> (HashMap<u32, Vec<usize>>, Gramophone) {
+    // In the order of their IDs, since the grams depend on the order of the input and builds should be reproducible
+    let mut ids: Vec<&u32> = sents.keys().collect();
+    ids.sort();
     let gram = Gramophone::from_word_iter(
-        sents
-            .values()
-            .map(|x| x.chars())
+        ids
+            .into_iter()
+            .map(|id| sents[id].chars()),
+        reserved,
     );
     let grammed_sents =
         sents
@@ -260,10 +493,49 @@ fn gramify_sentences(sents: HashMap<u32, String>) -> (HashMap<u32, Vec<usize>>,
 }
 
 impl Translation<String> {
-    fn gramify(self) -> (Translation<Vec<usize>>, Gramophone, Gramophone, Gramophone) {
-        let (prim_language, prim_gram) = gramify_sentences(self.prim_language);
-        let (sec_language, sec_gram) = gramify_sentences(self.sec_language);
-        let (aux_language, aux_gram) = gramify_sentences(self.aux_language);
+    // Returns the number of dropped sentences, along with the rejected characters of each language
+    fn filter_chars(&mut self, policy: CharPolicy) -> (usize, [CharReport; 3]) {
+        let mut reports = [CharReport::default(), CharReport::default(), CharReport::default()];
+        let mut n_dropped = 0;
+        n_dropped += filters::filter_chars(&mut self.prim_language, &PRIM_CHARS, policy, &mut reports[0]).len();
+        n_dropped += filters::filter_chars(&mut self.sec_language, &SEC_CHARS, policy, &mut reports[1]).len();
+        n_dropped += filters::filter_chars(&mut self.aux_language, &AUX_CHARS, policy, &mut reports[2]).len();
+
+        (n_dropped, reports)
+    }
+
+    // Returns the number of dropped sentences
+    fn filter_char_length(&mut self) -> usize {
+        filters::filter_length(&mut self.prim_language, PRIM_LIMIT.max_chars, |sent| sent.chars().count())
+        + filters::filter_length(&mut self.sec_language, SEC_LIMIT.max_chars, |sent| sent.chars().count())
+        + filters::filter_length(&mut self.aux_language, AUX_LIMIT.max_chars, |sent| sent.chars().count())
+    }
+
+    fn find_language_suspects(&self) -> Vec<langid::Suspect> {
+        langid::find_suspects(&[&self.prim_language, &self.sec_language, &self.aux_language], SUSPECT_MARGIN)
+    }
+
+    fn drop_language_suspects(&mut self, suspects: &[langid::Suspect]) {
+        for suspect in suspects {
+            self.language_mut(suspect.label as u8).remove(&suspect.id);
+        }
+    }
+
+    // Returns every replaced number, sorted by sentence ID
+    fn mask_numbers(&mut self, mode: NumberMode) -> Vec<(u32, numbers::MaskedNumber)> {
+        let mut masked = Vec::new();
+        mask_numbers_in_sentences(&mut self.prim_language, mode, &mut masked);
+        mask_numbers_in_sentences(&mut self.sec_language, mode, &mut masked);
+        mask_numbers_in_sentences(&mut self.aux_language, mode, &mut masked);
+
+        masked.sort_by_key(|&(id, ref num)| (id, num.slot));
+        masked
+    }
+
+    fn gramify(self, reserved: &[char]) -> (Translation<Vec<usize>>, Gramophone, Gramophone, Gramophone) {
+        let (prim_language, prim_gram) = gramify_sentences(self.prim_language, reserved);
+        let (sec_language, sec_gram) = gramify_sentences(self.sec_language, reserved);
+        let (aux_language, aux_gram) = gramify_sentences(self.aux_language, reserved);
 
         let trans = Translation {
             prim_language, sec_language, aux_language,
@@ -275,25 +547,148 @@ impl Translation<String> {
 }
 
 impl Translation<Vec<usize>> {
-    fn write_sentences<F: Write>(&self, file: &mut F, from: u8) -> Result<HashMap<u32, (usize, usize)>> {
+    // Returns the number of dropped sentences
+    fn filter_token_length(&mut self) -> usize {
+        filters::filter_length(&mut self.prim_language, PRIM_LIMIT.max_tokens, Vec::len)
+        + filters::filter_length(&mut self.sec_language, SEC_LIMIT.max_tokens, Vec::len)
+        + filters::filter_length(&mut self.aux_language, AUX_LIMIT.max_tokens, Vec::len)
+    }
+
+    fn length_histograms(&self) -> [Vec<usize>; 3] {
+        [
+            filters::length_histogram(&self.prim_language, Vec::len),
+            filters::length_histogram(&self.sec_language, Vec::len),
+            filters::length_histogram(&self.aux_language, Vec::len),
+        ]
+    }
+
+    // The number of tokens in the longest sentence of the link
+    fn link_length(&self, (prim_id, other_id): (u32, u32)) -> usize {
+        let other_len = self.sec_language.get(&other_id).or_else(|| self.aux_language.get(&other_id)).unwrap().len();
+        self.prim_language[&prim_id].len().max(other_len)
+    }
+
+    // Sorts by length, and then by ID
+    fn sort_links_by_length(&self, links: &mut [(u32, u32)]) {
+        links.sort_by_key(|&link| (self.link_length(link), link));
+    }
+
+    // Identifies the contents of a build: every sentence, link, gram and split, and the configuration
+    fn fingerprint(&self, grams: [&[tokens::Gram<char>]; 3], splits: &HashMap<u32, Split>, config: &[(String, String)]) -> Result<u64> {
+        let mut hasher = hash::Hasher64::new();
+
+        for sentences in &[&self.prim_language, &self.sec_language, &self.aux_language] {
+            let mut ids: Vec<&u32> = sentences.keys().collect();
+            ids.sort();
+            for id in ids {
+                hasher.write_u64(*id as u64);
+                for &token in &sentences[id] {
+                    hasher.write_u64(token as u64);
+                }
+            }
+        }
+
+        let mut links: Vec<&(u32, u32)> = self.links.iter().collect();
+        links.sort();
+        for &(prim_id, other_id) in links {
+            hasher.write_u64(prim_id as u64);
+            hasher.write_u64(other_id as u64);
+        }
+
+        for table in &grams {
+            tokens::encode_grams(&mut hasher, table)?;
+        }
+
+        let mut ids: Vec<&u32> = splits.keys().collect();
+        ids.sort();
+        for id in ids {
+            hasher.write_u64(*id as u64);
+            hasher.write_u64(splits[id] as u64);
+        }
+
+        // Lengths first, so that no two configurations hash the same bytes
+        for (name, value) in config {
+            for text in &[name, value] {
+                hasher.write_u64(text.len() as u64);
+                hasher.write(text.as_bytes());
+            }
+        }
+
+        Ok(hasher.finish())
+    }
+
+    // All tokens of one language in the order of sentence_ids, and the offset of each sentence in them,
+    // followed by the total number of tokens
+    fn token_stream(&self, from: u8) -> (Vec<usize>, Vec<u64>) {
+        let sentences = self.language(from);
+
+        let mut tokens = Vec::new();
+        let mut offsets = vec![0];
+        for id in self.sentence_ids(from) {
+            tokens.extend_from_slice(&sentences[&id]);
+            offsets.push(tokens.len() as u64);
+        }
+        (tokens, offsets)
+    }
+
+    fn n_tokens(&self, from: u8) -> usize {
+        let sentences = self.language(from);
+        sentences.values().map(Vec::len).sum()
+    }
+
+    // The IDs of the sentences in one language, in the order they are written
+    fn sentence_ids(&self, from: u8) -> Vec<u32> {
+        let sentences = self.language(from);
+        let mut ids: Vec<u32> = sentences.keys().cloned().collect();
+        ids.sort();
+        ids
+    }
+
+    // The size of a sentence file in records: tokens, or bytes when encoding the ranks of the tokens as varints
+    fn stream_size(&self, from: u8, ranks: Option<&[usize]>) -> usize {
+        let sentences = self.language(from);
+        match ranks {
+            Some(ranks) => sentences.values().flat_map(|sentence| sentence.iter()).map(|&token| varint::encoded_len(ranks[token])).sum(),
+            None => self.n_tokens(from),
+        }
+    }
+
+    fn write_sentences<F: Write>(&self, file: &mut F, from: u8, width: u8, ranks: Option<&[usize]>) -> Result<HashMap<u32, (usize, usize)>> {
         let mut id_offset_size: HashMap<u32, _> = HashMap::new();
         let mut offset = 0;
 
-        let sentences = match from {
-            0 => &self.prim_language,
-            1 => &self.sec_language,
-            2 => &self.aux_language,
-            _ => unimplemented!(),
-        };
+        let sentences = self.language(from);
 
-        for (&id, sentence) in sentences {
-            id_offset_size.insert(id, (offset, sentence.len() * 2));
+        let width = width as usize;
+        for id in self.sentence_ids(from) {
+            let sentence = &sentences[&id];
+
+            if let Some(ranks) = ranks {
+                let len: usize = sentence.iter().map(|&point| varint::encoded_len(ranks[point])).sum();
+                id_offset_size.insert(id, (offset, len));
+                for &point in sentence {
+                    varint::encode(file, ranks[point])?;
+                }
+                offset += len;
+                continue;
+            }
+
+            id_offset_size.insert(id, (offset, sentence.len() * width));
 
             for &point in sentence {
-                let point_u16: u16 = point.try_into().map_err(|_| Error::new(ErrorKind::InvalidData, format!("{} is too large to fit in a u16!", point)))?;
-                file.write(&point_u16.to_le_bytes())?;
+                match width {
+                    2 => {
+                        let point_u16: u16 = point.try_into().map_err(|_| Error::new(ErrorKind::InvalidData, format!("{} is too large to fit in a u16!", point)))?;
+                        file.write_all(&point_u16.to_le_bytes())?;
+                    }
+                    4 => {
+                        let point_u32: u32 = point.try_into().map_err(|_| Error::new(ErrorKind::InvalidData, format!("{} is too large to fit in a u32!", point)))?;
+                        file.write_all(&point_u32.to_le_bytes())?;
+                    }
+                    _ => unimplemented!(),
+                }
             }
-            offset += sentence.len() * 2;
+            offset += sentence.len() * width;
         }
 
         Ok(id_offset_size)
@@ -310,14 +705,14 @@ impl Gramophone {
     fn from_word_iter<
             I: IntoIterator<Item=J>,
             J: IntoIterator<Item=char>,
-        >(iter: I) -> Gramophone {
+        >(iter: I, reserved: &[char]) -> Gramophone {
         let mut inp = Vec::new();
         for word in iter {
             inp.extend(word.into_iter().flat_map(char::to_lowercase));
             inp.push('\0');
         }
 
-        let (_, grams) = tokens::encode_into_ngrams(inp, REL_LIM, |&x| x != '\0' && x.is_alphabetic());
+        let (_, grams) = tokens::encode_into_ngrams(inp, REL_LIM, MAX_GRAMS, reserved, |&x| x != '\0' && x.is_alphabetic());
 
         let mut i2idx = HashMap::new();
         for (idx, gram) in grams.iter().enumerate() {
@@ -367,77 +762,1056 @@ impl Gramophone {
     }
 }
 
+thread_local! {
+    // Where the build is read and written. Tests use a temporary directory instead, so they can run side by side.
+    static CACHE_DIR: std::cell::RefCell<String> = std::cell::RefCell::new("cache".to_string());
+}
+
 fn get_cache_path(filename: &str) -> String {
-    format!("cache/{}", filename)
+    CACHE_DIR.with(|dir| format!("{}/{}", dir.borrow(), filename))
+}
+
+fn write_npy_tokens(filename: &str, tokens: &[usize], width: u8) -> Result<()> {
+    let mut output = BufWriter::new(File::create(get_cache_path(&format!("{}/{}", NPY_DIR, filename)))?);
+    match width {
+        2 => {
+            let tokens: Vec<u16> = tokens.iter().map(|&t| t as u16).collect();
+            npy::write_npy(&mut output, &[tokens.len()], &tokens)?;
+        }
+        4 => {
+            let tokens: Vec<u32> = tokens.iter().map(|&t| t as u32).collect();
+            npy::write_npy(&mut output, &[tokens.len()], &tokens)?;
+        }
+        _ => return Err(Error::new(ErrorKind::InvalidInput, format!("tokens can't be {} bytes wide", width))),
+    }
+    output.flush()
+}
+
+// Writes sentences-{name}.bin, its index and, for varints, its ranks. Returns the offset and length of each sentence.
+fn write_sentence_file(
+    sent_ngram: &Translation<Vec<usize>>,
+    from: u8, name: &str, language: &str, n_grams: usize, token_width: u8, fingerprint: u64,
+) -> Result<HashMap<u32, (usize, usize)>> {
+    let sentences = sent_ngram.language(from);
+
+    let ranks = if VARINT_SENTENCES {
+        let (ranks, grams_by_rank) = varint::frequency_ranks(sentences.values(), n_grams);
+
+        let mut ranks_output = create_artifact(&format!("ranks-{}.bin", name), Kind::Ranks, 4, language, grams_by_rank.len(), fingerprint)?;
+        for gram in grams_by_rank {
+            ranks_output.write_all(&(gram as u32).to_le_bytes())?;
+        }
+        ranks_output.flush()?;
+
+        Some(ranks)
+    } else {
+        None
+    };
+    let ranks = ranks.as_ref().map(|ranks| &ranks[..]);
+
+    let (kind, width) = if VARINT_SENTENCES { (Kind::Varints, 1) } else { (Kind::Sentences, token_width) };
+    let mut output = create_artifact(&format!("sentences-{}.bin", name), kind, width, language, sent_ngram.stream_size(from, ranks), fingerprint)?;
+    let meta = sent_ngram.write_sentences(&mut output, from, token_width, ranks)?;
+    output.flush()?;
+
+    let ids = sent_ngram.sentence_ids(from);
+    let mut index_output = create_artifact(&format!("index-{}.bin", name), Kind::Index, 8, language, ids.len() + 1, fingerprint)?;
+    let mut end = 0;
+    for id in &ids {
+        let (offset, len) = meta[id];
+        index_output.write_all(&(offset as u64).to_le_bytes())?;
+        end = offset + len;
+    }
+    index_output.write_all(&(end as u64).to_le_bytes())?;
+    index_output.flush()?;
+
+    Ok(meta)
+}
+
+// The sentences of a language which are in the train split
+fn train_sentences<'a>(sents: &'a HashMap<u32, Vec<usize>>, splits: &HashMap<u32, Split>) -> Vec<&'a Vec<usize>> {
+    sents.iter().filter(|&(id, _)| splits.get(id) == Some(&Split::Train)).map(|(_, sent)| sent).collect()
+}
+
+// Links as rows of (primary sentence index, other sentence index)
+fn write_npy_links(filename: &str, links: &[(u32, u32)], sentence_index: &HashMap<u32, u32>) -> Result<()> {
+    let mut indices = Vec::with_capacity(links.len() * 2);
+    for &(prim_id, other_id) in links {
+        indices.push(sentence_index[&prim_id]);
+        indices.push(sentence_index[&other_id]);
+    }
+
+    let mut output = BufWriter::new(File::create(get_cache_path(&format!("{}/{}", NPY_DIR, filename)))?);
+    npy::write_npy(&mut output, &[links.len(), 2], &indices)?;
+    output.flush()
+}
+
+// The columns of the Arrow exports, see WRITE_ARROW
+fn arrow_fields() -> Vec<arrow::Field> {
+    use arrow::DataType::*;
+    [
+        ("split", Utf8),
+        ("prim_id", UInt32),
+        ("other_id", UInt32),
+        ("prim_text", Utf8),
+        ("other_text", Utf8),
+        ("prim_tokens", ListUInt32),
+        ("other_tokens", ListUInt32),
+    ]
+    .iter()
+    .map(|&(name, data_type)| arrow::Field { name, data_type })
+    .collect()
+}
+
+// Creates a binary file in the cache directory, starting with its header
+fn create_artifact(filename: &str, kind: Kind, width: u8, language: &str, count: usize, fingerprint: u64) -> Result<BufWriter<File>> {
+    let mut file = BufWriter::new(File::create(get_cache_path(filename))?);
+    let header = Header { kind, width, language: language.to_string(), count: count as u64, fingerprint };
+    header.write(&mut file)?;
+    Ok(file)
+}
+
+// One line per rejected character: language, character, code point, number of occurrences, first sentence ID
+fn write_char_reports<F: Write>(file: &mut F, reports: &[CharReport; 3]) -> Result<()> {
+    for (language, report) in [PRIM_LANGUAGE, SEC_LANGUAGE, AUX_LANGUAGE].iter().zip(reports.iter()) {
+        for (ch, count, id) in report.sorted() {
+            writeln!(file, "{}\t{:?}\tU+{:04X}\t{}\t{}", language, ch, ch as u32, count, id)?;
+        }
+    }
+    Ok(())
+}
+
+// One line per language and length: language, number of tokens, number of sentences
+fn write_length_histograms<F: Write>(file: &mut F, histograms: &[Vec<usize>; 3]) -> Result<()> {
+    for (language, histogram) in [PRIM_LANGUAGE, SEC_LANGUAGE, AUX_LANGUAGE].iter().zip(histograms.iter()) {
+        for (n, &count) in histogram.iter().enumerate() {
+            if count != 0 {
+                writeln!(file, "{}\t{}\t{}", language, n, count)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// One line per suspect: sentence ID, tagged language, guessed language, margin, sentence
+fn write_language_suspects<F: Write>(file: &mut F, suspects: &[langid::Suspect], sentences: &Translation<String>) -> Result<()> {
+    let languages = [PRIM_LANGUAGE, SEC_LANGUAGE, AUX_LANGUAGE];
+    let sents = [&sentences.prim_language, &sentences.sec_language, &sentences.aux_language];
+
+    for suspect in suspects {
+        writeln!(
+            file, "{}\t{}\t{}\t{:.3}\t{}",
+            suspect.id, languages[suspect.label], languages[suspect.guess], suspect.margin, sents[suspect.label][&suspect.id],
+        )?;
+    }
+    Ok(())
+}
+
+// One line per masked number: sentence ID, number token slot, original value
+fn write_masked_numbers<F: Write>(file: &mut F, masked: &[(u32, numbers::MaskedNumber)]) -> Result<()> {
+    for (id, num) in masked {
+        writeln!(file, "{}\t{}\t{}", id, num.slot, num.original)?;
+    }
+    Ok(())
+}
+
+fn write_epoch_permutations(n_epochs: usize) -> Result<()> {
+    let sec_path = get_cache_path("sec-links-train.bin");
+    let aux_path = get_cache_path("aux-links-train.bin");
+    let (sec_header, _) = format::open(&sec_path, Kind::Links)?;
+    let (aux_header, _) = format::open(&aux_path, Kind::Links)?;
+    format::check_same_build(&[(&sec_path, &sec_header), (&aux_path, &aux_header)])?;
+
+    for &(name, links_header) in &[("sec", &sec_header), ("aux", &aux_header)] {
+        let n_links = links_header.count as usize;
+        println!("Writing {} permutations of {} {} links", n_epochs, n_links, name);
+
+        let mut epochs_output = create_artifact(
+            &format!("{}-epochs.bin", name), Kind::Permutations, 4,
+            &links_header.language, n_links * n_epochs, links_header.fingerprint,
+        )?;
+        sampling::write_permutations(&mut epochs_output, n_links, EPOCH_SEED, n_epochs)?;
+        epochs_output.flush()?;
+    }
+    Ok(())
+}
+
+// Rewrites the permutations of an existing build, and updates its manifest and container to match
+fn permute(n_epochs: usize) -> Result<()> {
+    write_epoch_permutations(n_epochs)?;
+
+    // Otherwise verify would find that the permutations no longer match the manifest. A manifest of another build is
+    // left alone, since its container would mix files of both builds if it was packed again.
+    let fingerprint = format!("{:016x}", latest_fingerprint()?);
+    let mut manifest = match manifest::Manifest::read(&get_cache_path(MANIFEST_FILE)) {
+        Ok(manifest) => manifest,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    if !manifest.info.iter().any(|(name, value)| name == "fingerprint" && *value == fingerprint) {
+        println!("The manifest is of another build, not updating it");
+        return Ok(());
+    }
+
+    let mut names = vec!["sec-epochs.bin", "aux-epochs.bin"];
+    if manifest.files.iter().any(|entry| entry.name == CONTAINER_FILE) {
+        pack(&get_cache_path(CONTAINER_FILE))?;
+        names.push(CONTAINER_FILE);
+    }
+    for name in names {
+        manifest.refresh(name, &get_cache_path(name))?;
+    }
+
+    let mut manifest_output = BufWriter::new(File::create(get_cache_path(MANIFEST_FILE))?);
+    manifest.write(&mut manifest_output)?;
+    manifest_output.flush()?;
+    println!("Updated the manifest");
+
+    Ok(())
+}
+
+fn parse_arg<T: FromStr>(args: &[String], idx: usize, default: T, what: &str) -> Result<T> {
+    match args.get(idx) {
+        Some(arg) => arg.parse().map_err(|_| Error::new(ErrorKind::InvalidInput, format!("invalid {}: {:?}", what, arg))),
+        None => Ok(default),
+    }
 }
 
 fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+
+    match args.get(1).map(String::as_str) {
+        None | Some("build") => build(),
+        Some("permute") => permute(parse_arg(&args, 2, N_EPOCHS, "number of epochs")?),
+        Some("review") => write_review(parse_arg(&args, 2, REVIEW_PER_STRATUM, "number of pairs per stratum")?, parse_arg(&args, 3, 0, "seed")?),
+        Some("apply-review") => apply_review(&parse_arg(&args, 2, get_cache_path(REVIEW_FILE), "review file")?),
+        Some("pack") => pack(&parse_arg(&args, 2, get_cache_path(CONTAINER_FILE), "container file")?),
+        Some("unpack") => unpack(&parse_arg(&args, 2, get_cache_path(CONTAINER_FILE), "container file")?),
+        Some("verify") => verify_build(),
+        Some("inspect") => inspect_pairs(&args),
+        Some(command) => Err(Error::new(ErrorKind::InvalidInput, format!("unknown command {:?}, expected build, permute, review, apply-review, pack, unpack, verify or inspect", command))),
+    }
+}
+
+// select-langs inspect [sec|aux] [random|<link index>|id <Tatoeba ID>] [split]
+// Prints a random link or the link at an index of a split (train by default), or all links of a sentence
+fn inspect_pairs(args: &[String]) -> Result<()> {
+    let task_name: String = parse_arg(args, 2, "sec".to_string(), "task")?;
+    let task = reader::Task::from_name(&task_name).ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("invalid task {:?}, expected sec or aux", task_name)))?;
+    let selector: String = parse_arg(args, 3, "random".to_string(), "link")?;
+
+    let build = reader::Build::open(&get_cache_path(""))?;
+
+    if selector == "id" {
+        let id: u32 = parse_arg(args, 4, 0, "sentence ID")?;
+        let offsets = (
+            build.prim.sentence_by_id(id)?.map(|sentence| sentence.offset),
+            build.other(task).sentence_by_id(id)?.map(|sentence| sentence.offset),
+        );
+
+        let mut n_found = 0;
+        for &split in &Split::ALL {
+            for pair in build.pairs(task, split).iter() {
+                let pair = pair?;
+                if offsets.0 == Some(pair.prim.offset) || offsets.1 == Some(pair.other.offset) {
+                    inspect::print_pair(&format!("{}-links-{}.bin link {}", task.name(), split.name(), pair.index), &pair)?;
+                    n_found += 1;
+                }
+            }
+        }
+        println!("Found {} links of sentence {}", n_found, id);
+        return Ok(());
+    }
+
+    let split_name: String = parse_arg(args, 4, "train".to_string(), "split")?;
+    let split = Split::from_name(&split_name).ok_or_else(|| Error::new(ErrorKind::InvalidInput, format!("invalid split {:?}", split_name)))?;
+    let pairs = build.pairs(task, split);
+
+    let pair = if selector == "random" {
+        let seed = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+        pairs.random_pair(&mut rng::Rng::new(seed))?
+    } else {
+        pairs.pair(parse_arg(args, 3, 0, "link index")?)?
+    };
+    inspect::print_pair(&format!("{}-links-{}.bin link {}", task.name(), split.name(), pair.index), &pair)
+}
+
+fn latest_fingerprint() -> Result<u64> {
+    let (grams_header, _) = format::open(&get_cache_path("ngrams-prim.bin"), Kind::Grams)?;
+    Ok(grams_header.fingerprint)
+}
+
+// The binary files in cache/ from the build with the given fingerprint, except for containers, sorted by name.
+// Files left over from earlier builds are recognized by their fingerprint and left out.
+fn build_files(fingerprint: u64) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    for entry in std::fs::read_dir(get_cache_path(""))? {
+        let name = entry?.file_name().to_string_lossy().into_owned();
+        if !name.ends_with(".bin") {
+            continue;
+        }
+        let mut file = BufReader::new(File::open(get_cache_path(&name))?);
+        match Header::read(&mut file) {
+            Ok(ref header) if header.fingerprint == fingerprint && header.kind != Kind::Container => names.push(name),
+            Ok(_) => println!("Leaving out {}, which is from another build", name),
+            Err(_) => println!("Leaving out {}, which is not a select-langs file", name),
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+// The configuration constants which affect the output
+fn config() -> Vec<(String, String)> {
+    let char_set = |chars: &CharSet| format!("{:?} {:?}", chars.scripts, chars.extra);
+    let limit = |limit: &LengthLimit| format!("chars {:?}, tokens {:?}", limit.max_chars, limit.max_tokens);
+
+    vec![
+        ("PRIM_LANGUAGE", PRIM_LANGUAGE.to_string()),
+        ("SEC_LANGUAGE", SEC_LANGUAGE.to_string()),
+        ("AUX_LANGUAGE", AUX_LANGUAGE.to_string()),
+        ("REL_LIM", REL_LIM.to_string()),
+        ("MAX_GRAMS", MAX_GRAMS.to_string()),
+        ("NUMBER_MODE", format!("{:?}", NUMBER_MODE)),
+        ("PRIM_CHARS", char_set(&PRIM_CHARS)),
+        ("SEC_CHARS", char_set(&SEC_CHARS)),
+        ("AUX_CHARS", char_set(&AUX_CHARS)),
+        ("CHAR_POLICY", format!("{:?}", CHAR_POLICY)),
+        ("PRIM_LIMIT", limit(&PRIM_LIMIT)),
+        ("SEC_LIMIT", limit(&SEC_LIMIT)),
+        ("AUX_LIMIT", limit(&AUX_LIMIT)),
+        ("CHECK_LANGUAGES", CHECK_LANGUAGES.to_string()),
+        ("DROP_SUSPECT_LANGUAGES", DROP_SUSPECT_LANGUAGES.to_string()),
+        ("SUSPECT_MARGIN", SUSPECT_MARGIN.to_string()),
+        ("DEV_FRACTION", DEV_FRACTION.to_string()),
+        ("TEST_FRACTION", TEST_FRACTION.to_string()),
+        ("SPLIT_KEY", format!("{:x}", SPLIT_KEY)),
+        ("N_FOLDS", format!("{:?}", N_FOLDS)),
+        ("FOLD_KEY", format!("{:x}", FOLD_KEY)),
+        ("BUCKET_WIDTH", BUCKET_WIDTH.to_string()),
+        ("N_EPOCHS", N_EPOCHS.to_string()),
+        ("EPOCH_SEED", format!("{:x}", EPOCH_SEED)),
+        ("TASK_TEMPERATURE", TASK_TEMPERATURE.to_string()),
+        ("BALANCE_PRIM_SENTENCES", BALANCE_PRIM_SENTENCES.to_string()),
+        (
+            "CURRICULUM_WEIGHTS",
+            format!("length {}, rarity {}, ratio {}", CURRICULUM_WEIGHTS.length, CURRICULUM_WEIGHTS.rarity, CURRICULUM_WEIGHTS.ratio),
+        ),
+        ("BINARY_MODE", BINARY_MODE.to_string()),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value))
+    .collect()
+}
+
+fn write_manifest(fingerprint: u64, info: &[(String, String)]) -> Result<()> {
+    let mut names = build_files(fingerprint)?;
+    names.extend([BUILD_INFO_FILE, SPLITS_FILE, "numbers.tsv", "lengths.tsv", "rejected-chars.tsv"].iter().map(|name| name.to_string()));
+    if CHECK_LANGUAGES {
+        names.push("suspect-languages.tsv".to_string());
+    }
+    if WRITE_NPY {
+        for name in &["prim", "sec", "aux"] {
+            names.push(format!("{}/tokens-{}.npy", NPY_DIR, name));
+            names.push(format!("{}/offsets-{}.npy", NPY_DIR, name));
+        }
+        for &split in &Split::ALL {
+            names.push(format!("{}/sec-links-{}.npy", NPY_DIR, split.name()));
+            names.push(format!("{}/aux-links-{}.npy", NPY_DIR, split.name()));
+        }
+    }
+    if WRITE_JSONL {
+        names.push(JSONL_FILE.to_string());
+    }
+    if WRITE_ARROW {
+        for other in &[SEC_LANGUAGE, AUX_LANGUAGE] {
+            names.push(format!("{}/{}-{}.arrow", ARROW_DIR, PRIM_LANGUAGE, other));
+        }
+    }
+    if WRITE_CONTAINER {
+        names.push(CONTAINER_FILE.to_string());
+    }
+
+    let mut manifest = manifest::Manifest::default();
+    for name in &names {
+        manifest.files.push(manifest::FileEntry::from_file(name, &get_cache_path(name))?);
+    }
+
+    // The block- and allowlists are optional
+    for name in &["raw/sentences.tsv", "raw/links.tsv", BLOCKLIST_FILE, ALLOWLIST_FILE] {
+        match manifest::FileEntry::from_file(name, &get_cache_path(name)) {
+            Ok(entry) => manifest.inputs.push(entry),
+            Err(ref e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    manifest.config = config();
+    manifest.info = info.to_vec();
+
+    let mut manifest_output = BufWriter::new(File::create(get_cache_path(MANIFEST_FILE))?);
+    manifest.write(&mut manifest_output)?;
+    manifest_output.flush()?;
+    println!("Wrote manifest of {} files", manifest.files.len());
+
+    Ok(())
+}
+
+// Checks the build in cache/ against its manifest, and checks that its files agree with each other
+fn verify_build() -> Result<()> {
+    let mut n_problems = 0;
+    let mut problem = |msg: String| {
+        println!("Problem: {}", msg);
+        n_problems += 1;
+    };
+
+    match manifest::Manifest::read(&get_cache_path(MANIFEST_FILE)) {
+        Ok(manifest) => {
+            for entry in &manifest.files {
+                match manifest::FileEntry::from_file(&entry.name, &get_cache_path(&entry.name)) {
+                    Ok(ref found) if found == entry => {}
+                    Ok(found) => problem(format!(
+                        "{} has size {} and checksum {:08x}, expected {} and {:08x}",
+                        entry.name, found.size, found.checksum, entry.size, entry.checksum,
+                    )),
+                    Err(e) => problem(format!("{}: {}", entry.name, e)),
+                }
+            }
+            println!("Checked {} files against the manifest", manifest.files.len());
+
+            // Changed inputs don't make the build invalid, but it can't be reproduced from them anymore
+            for entry in &manifest.inputs {
+                match manifest::FileEntry::from_file(&entry.name, &get_cache_path(&entry.name)) {
+                    Ok(ref found) if found == entry => {}
+                    _ => println!("Note: input {} has changed since the build", entry.name),
+                }
+            }
+        }
+        Err(ref e) if e.kind() == ErrorKind::NotFound => println!("No manifest, only checking that the files agree with each other"),
+        Err(e) => return Err(e),
+    }
+
+    let mut sentences = HashMap::new();
+    for &name in &["prim", "sec", "aux"] {
+        let grams_path = get_cache_path(&format!("ngrams-{}.bin", name));
+        let sentences_path = get_cache_path(&format!("sentences-{}.bin", name));
+
+        let ranks_path = get_cache_path(&format!("ranks-{}.bin", name));
+        let index_path = get_cache_path(&format!("index-{}.bin", name));
+
+        match verify::check_grams(&grams_path) {
+            Ok(n_grams) => match verify::check_sentences(&sentences_path, &ranks_path, n_grams) {
+                Ok(header) => match verify::check_index(&index_path, &header) {
+                    Ok(spans) => {
+                        println!("Checked {} grams and {} tokens of {}", n_grams, header.count, header.language);
+                        sentences.insert(name, spans);
+                    }
+                    Err(e) => problem(e.to_string()),
+                },
+                Err(e) => problem(e.to_string()),
+            },
+            Err(e) => problem(e.to_string()),
+        }
+    }
+
+    let mut links_files: Vec<(&str, String)> = Vec::new();
+    for &name in &["sec", "aux"] {
+        for &split in &Split::ALL {
+            links_files.push((name, format!("{}-links-{}.bin", name, split.name())));
+        }
+        links_files.push((name, format!("{}-curriculum.bin", name)));
+    }
+    for (name, links_file) in links_files {
+        if let (Some(prim), Some(other)) = (sentences.get("prim"), sentences.get(name)) {
+            match verify::check_links(&get_cache_path(&links_file), prim, other) {
+                Ok(n_links) => println!("Checked {} links in {}", n_links, links_file),
+                Err(e) => problem(e.to_string()),
+            }
+        }
+    }
+
+    if n_problems > 0 {
+        return Err(Error::new(ErrorKind::InvalidData, format!("found {} problems in the build", n_problems)));
+    }
+    println!("The build is fine");
+    Ok(())
+}
+
+// Packs the binary files of the latest build in cache/, along with BUILD_INFO_FILE, into a container
+fn pack(path: &str) -> Result<()> {
+    let fingerprint = latest_fingerprint()?;
+    let mut names = build_files(fingerprint)?;
+    names.push(BUILD_INFO_FILE.to_string());
+
+    let files: Vec<(String, String)> = names.iter().map(|name| (name.clone(), get_cache_path(name))).collect();
+    let sections = container::write_container(path, fingerprint, &files)?;
+    println!("Packed {} files into {}", sections.len(), path);
+
+    container::Container::open(path)?.verify()
+}
+
+// Writes all sections of a container back into cache/ as loose files
+fn unpack(path: &str) -> Result<()> {
+    let mut container = container::Container::open(path)?;
+    println!("Unpacking build {:016x} from {}", container.header.fingerprint, path);
+
+    for section in container.sections.clone() {
+        if section.name.contains('/') || section.name.starts_with('.') {
+            return Err(Error::new(ErrorKind::InvalidData, format!("refusing to unpack section {:?}", section.name)));
+        }
+        let mut out = BufWriter::new(File::create(get_cache_path(&section.name))?);
+        container.copy_section(&section, &mut out)?;
+        out.flush()?;
+        println!("Unpacked {} ({} bytes)", section.name, section.length);
+    }
+    Ok(())
+}
+
+fn write_review(per_stratum: usize, seed: u64) -> Result<()> {
+    let Loaded { mut sentences, unfiltered, mut dropped } = load_filtered(true)?;
+    let unfiltered = unfiltered.unwrap(); // Always kept when asked for
+
+    // The token length filter needs the grams, so the sentences are gramified like in build
+    sentences.mask_numbers(NUMBER_MODE);
+    let (mut sent_ngram, _, _, _) = sentences.gramify(&reserved_tokens());
+    let links_before = sent_ngram.links.clone();
+    sent_ngram.filter_token_length();
+    sent_ngram.remove_dangling();
+    record_dropped(&links_before, &sent_ngram.links, "token length", &mut dropped);
+
+    let sec_links: Vec<(u32, u32)> =
+        unfiltered.links
+        .iter()
+        .filter(|&&(_, other_id)| unfiltered.sec_language.contains_key(&other_id))
+        .cloned()
+        .collect();
+
+    let sec_ids = sec_links.iter().map(|&(_, other_id)| other_id).collect();
+    let contributors = review::read_contributors(&get_cache_path("raw/sentences_detailed.tsv"), &sec_ids)?;
+    println!("Found contributors of {}/{} sentences", contributors.len(), sec_ids.len());
+
+    let pairs =
+        sec_links
+        .into_iter()
+        .map(|(prim_id, other_id)| {
+            let prim_text = unfiltered.prim_language[&prim_id].clone();
+            let other_text = unfiltered.sec_language[&other_id].clone();
+            let stratum = review::Stratum {
+                length_bucket: review::length_bucket(prim_text.chars().count().max(other_text.chars().count())),
+                contributor: contributors.get(&other_id).cloned().unwrap_or_else(|| "unknown".to_string()),
+                filter: dropped.get(&(prim_id, other_id)).cloned(),
+            };
+            review::ReviewPair { prim_id, other_id, prim_text, other_text, stratum }
+        })
+        .collect();
+
+    let sample = review::stratified_sample(pairs, per_stratum, &mut rng::Rng::new(seed));
+    println!("Writing {} pairs for review", sample.len());
+
+    let mut review_output = BufWriter::new(File::create(get_cache_path(REVIEW_FILE))?);
+    review::write_review(&mut review_output, &sample, PRIM_LANGUAGE, SEC_LANGUAGE)?;
+    review_output.flush()?;
+
+    Ok(())
+}
+
+fn apply_review(path: &str) -> Result<()> {
+    let (bad, n_unreviewed) = review::read_bad_links(path)?;
+    println!("Blocking {} bad pairs ({} pairs have not been reviewed)", bad.len(), n_unreviewed);
+
+    let mut blocklist = OpenOptions::new().create(true).append(true).open(get_cache_path(BLOCKLIST_FILE))?;
+    writeln!(blocklist, "# Bad pairs from {}", path)?;
+    for (prim_id, other_id) in bad {
+        writeln!(blocklist, "{}\t{}", prim_id, other_id)?;
+    }
+
+    Ok(())
+}
+
+// The sentences after all filters which work on the raw text
+struct Loaded {
+    sentences: Translation<String>,
+    // Only kept for review: the sentences before filtering, and which filter removed each dropped link
+    unfiltered: Option<Translation<String>>,
+    dropped: HashMap<(u32, u32), &'static str>,
+}
+
+fn record_dropped(before: &HashSet<(u32, u32)>, after: &HashSet<(u32, u32)>, filter: &'static str, dropped: &mut HashMap<(u32, u32), &'static str>) {
+    for link in before.difference(after) {
+        dropped.insert(*link, filter);
+    }
+}
+
+// Loads the sentences and links and applies the filters which work on text. For review, the unfiltered sentences are
+// kept as well, and the reports are not written, so that those of the last build stay as they are.
+fn load_filtered(for_review: bool) -> Result<Loaded> {
     let sentence_file = BufReader::new(File::open(get_cache_path("raw/sentences.tsv"))?);
     let links_file = BufReader::new(File::open(get_cache_path("raw/links.tsv"))?);
 
+    let id_filter = IdFilter {
+        block: IdList::from_file(&get_cache_path(BLOCKLIST_FILE))?,
+        allow: IdList::from_file(&get_cache_path(ALLOWLIST_FILE))?,
+    };
+    println!(
+        "Blocking {} sentences and {} links, allowing {} sentences and {} links",
+        id_filter.block.sentences.len(), id_filter.block.links.len(),
+        id_filter.allow.sentences.len(), id_filter.allow.links.len(),
+    );
+
     let mut sentences = Translation::new();
 
     println!("Consuming sentences");
-    sentences.consume_sentences(sentence_file)?;
-    println!("Loaded {:?}/{:?}/{:?}", sentences.prim_language.len(), sentences.sec_language.len(), sentences.aux_language.len());
+    let (_, excluded) = sentences.consume_sentences(sentence_file, &id_filter)?;
+    println!("Loaded {:?}/{:?}/{:?} ({:?} were excluded)", sentences.prim_language.len(), sentences.sec_language.len(), sentences.aux_language.len(), excluded);
 
     println!("Consuming links");
-    let (read, wrong) = sentences.consume_links(links_file, true)?;
-    println!("Loaded {:?} links ({:?} were wrong)", read, wrong);
+    let (read, wrong, excluded) = sentences.consume_links(links_file, true, &id_filter)?;
+    println!("Loaded {:?} links ({:?} were wrong, {:?} were excluded)", read, wrong, excluded);
 
     println!("After filter {:?}/{:?}/{:?}", sentences.prim_language.len(), sentences.sec_language.len(), sentences.aux_language.len());
 
     println!("Stringifying");
-    let sent_string = sentences.stringify()?;
+    let mut sent_string = sentences.stringify()?;
+
+    let unfiltered = if for_review { Some(sent_string.clone()) } else { None };
+    let mut dropped = HashMap::new();
+
+    println!("Filtering characters ({:?})", CHAR_POLICY);
+    let links_before = if for_review { sent_string.links.clone() } else { HashSet::new() };
+    let (n_dropped, char_reports) = sent_string.filter_chars(CHAR_POLICY);
+    let n_removed_links = sent_string.remove_dangling();
+    record_dropped(&links_before, &sent_string.links, "characters", &mut dropped);
+    println!(
+        "Found {}/{}/{} sentences with rejected characters, dropped {} sentences and {} links",
+        char_reports[0].n_sentences, char_reports[1].n_sentences, char_reports[2].n_sentences,
+        n_dropped, n_removed_links,
+    );
+
+    if !for_review {
+        let mut chars_output = BufWriter::new(File::create(get_cache_path("rejected-chars.tsv"))?);
+        write_char_reports(&mut chars_output, &char_reports)?;
+        chars_output.flush()?;
+    }
+
+    println!("Filtering by character length");
+    let links_before = if for_review { sent_string.links.clone() } else { HashSet::new() };
+    let n_dropped = sent_string.filter_char_length();
+    let n_removed_links = sent_string.remove_dangling();
+    record_dropped(&links_before, &sent_string.links, "length", &mut dropped);
+    println!("Dropped {} sentences and {} links", n_dropped, n_removed_links);
+
+    if CHECK_LANGUAGES {
+        println!("Looking for sentences with the wrong language");
+        let suspects = sent_string.find_language_suspects();
+        println!("Found {} suspects", suspects.len());
+
+        if !for_review {
+            let mut suspects_output = BufWriter::new(File::create(get_cache_path("suspect-languages.tsv"))?);
+            write_language_suspects(&mut suspects_output, &suspects, &sent_string)?;
+            suspects_output.flush()?;
+        }
+
+        if DROP_SUSPECT_LANGUAGES {
+            let links_before = if for_review { sent_string.links.clone() } else { HashSet::new() };
+            sent_string.drop_language_suspects(&suspects);
+            let n_removed_links = sent_string.remove_dangling();
+            record_dropped(&links_before, &sent_string.links, "language", &mut dropped);
+            println!("Dropped {} sentences and {} links", suspects.len(), n_removed_links);
+        }
+    }
+
+    Ok(Loaded { sentences: sent_string, unfiltered, dropped })
+}
+
+// The tokens which get the first grams of every language
+fn reserved_tokens() -> Vec<char> {
+    let mut reserved = numbers::reserved_tokens(NUMBER_MODE);
+    reserved.extend(filters::reserved_tokens(CHAR_POLICY));
+    reserved
+}
+
+fn build() -> Result<()> {
+    // Checked before the slow part of the build, since each fold has to fit in a byte
+    if let Some(n_folds) = N_FOLDS {
+        if !(1..=256).contains(&n_folds) {
+            return Err(Error::new(ErrorKind::InvalidInput, format!("N_FOLDS must be between 1 and 256, not {}", n_folds)));
+        }
+    }
+
+    let mut sent_string = load_filtered(false)?.sentences;
+    let raw_text = if WRITE_JSONL || WRITE_ARROW { Some(sent_string.clone()) } else { None };
+
+    println!("Masking numbers ({:?})", NUMBER_MODE);
+    let masked = sent_string.mask_numbers(NUMBER_MODE);
+    println!("Replaced {} numbers", masked.len());
+
+    let mut numbers_output = BufWriter::new(File::create(get_cache_path("numbers.tsv"))?);
+    write_masked_numbers(&mut numbers_output, &masked)?;
+    numbers_output.flush()?;
+
     println!("Gramifying");
-    let (sent_ngram, prim_gram, sec_gram, aux_gram) = sent_string.gramify();
+    let (mut sent_ngram, prim_gram, sec_gram, aux_gram) = sent_string.gramify(&reserved_tokens());
     println!("{} / {} / {} grams", prim_gram.grams.len(), sec_gram.grams.len(), aux_gram.grams.len());
 
+    let histograms = sent_ngram.length_histograms();
+    for (language, histogram) in [PRIM_LANGUAGE, SEC_LANGUAGE, AUX_LANGUAGE].iter().zip(histograms.iter()) {
+        println!(
+            "Token lengths for {}: median {}, 90% {}, 99% {}, max {}",
+            language,
+            filters::histogram_quantile(histogram, 0.5),
+            filters::histogram_quantile(histogram, 0.9),
+            filters::histogram_quantile(histogram, 0.99),
+            histogram.len().saturating_sub(1),
+        );
+        filters::print_histogram(histogram, 5);
+    }
+
+    let mut lengths_output = BufWriter::new(File::create(get_cache_path("lengths.tsv"))?);
+    write_length_histograms(&mut lengths_output, &histograms)?;
+    lengths_output.flush()?;
+
+    println!("Filtering by token length");
+    let n_dropped = sent_ngram.filter_token_length();
+    let n_removed_links = sent_ngram.remove_dangling();
+    println!("Dropped {} sentences and {} links", n_dropped, n_removed_links);
+
+
+    println!("Splitting into train/dev/test");
+    let previous_splits = splits::read_splits(&get_cache_path(SPLITS_FILE))?;
+    let splits = splits::assign_splits(&sent_ngram.links, DEV_FRACTION, TEST_FRACTION, SPLIT_KEY, &previous_splits);
+    let n_moved = splits.iter().filter(|&(id, split)| previous_splits.get(id).is_some_and(|previous| previous != split)).count();
+    println!(
+        "Kept the splits of {} sentences from the previous build, moved {} into a stricter split",
+        splits.keys().filter(|id| previous_splits.contains_key(id)).count() - n_moved, n_moved,
+    );
+
+    // Sentences which are left out of this build keep their splits for the next one
+    let mut all_splits = previous_splits;
+    all_splits.extend(splits.iter().map(|(&id, &split)| (id, split)));
+
+    let mut splits_output = BufWriter::new(File::create(get_cache_path(SPLITS_FILE))?);
+    splits::write_splits(&mut splits_output, &all_splits)?;
+    splits_output.flush()?;
+
+    let fingerprint = sent_ngram.fingerprint([&prim_gram.grams, &sec_gram.grams, &aux_gram.grams], &splits, &config())?;
+    println!("Build fingerprint {:016x}", fingerprint);
 
     println!("Writing primary ngrams");
-    let mut prim_ngrams = BufWriter::new(File::create(get_cache_path("ngrams-prim.bin"))?);
-    tokens::encode_grams(&mut prim_ngrams, prim_gram.grams)?;
+    let mut prim_ngrams = create_artifact("ngrams-prim.bin", Kind::Grams, 4, PRIM_LANGUAGE, prim_gram.grams.len(), fingerprint)?;
+    tokens::encode_grams(&mut prim_ngrams, &prim_gram.grams)?;
     prim_ngrams.flush()?;
 
     println!("Writing secondary ngrams");
-    let mut sec_ngrams = BufWriter::new(File::create(get_cache_path("ngrams-sec.bin"))?);
-    tokens::encode_grams(&mut sec_ngrams, sec_gram.grams)?;
+    let mut sec_ngrams = create_artifact("ngrams-sec.bin", Kind::Grams, 4, SEC_LANGUAGE, sec_gram.grams.len(), fingerprint)?;
+    tokens::encode_grams(&mut sec_ngrams, &sec_gram.grams)?;
     sec_ngrams.flush()?;
 
     println!("Writing auxiliary ngrams");
-    let mut aux_ngrams = BufWriter::new(File::create(get_cache_path("ngrams-aux.bin"))?);
-    tokens::encode_grams(&mut aux_ngrams, aux_gram.grams)?;
+    let mut aux_ngrams = create_artifact("ngrams-aux.bin", Kind::Grams, 4, AUX_LANGUAGE, aux_gram.grams.len(), fingerprint)?;
+    tokens::encode_grams(&mut aux_ngrams, &aux_gram.grams)?;
     aux_ngrams.flush()?;
 
+    let token_width = token_width(prim_gram.grams.len().max(sec_gram.grams.len()).max(aux_gram.grams.len()));
+    if VARINT_SENTENCES {
+        println!("Writing sentences as varints");
+    } else {
+        println!("Writing sentences with {}-bit tokens", token_width * 8);
+    }
+
     println!("Writing primary sentences");
-    let mut prim_output = BufWriter::new(File::create(get_cache_path("sentences-prim.bin"))?);
-    let mut meta = sent_ngram.write_sentences(&mut prim_output, 0)?;
-    prim_output.flush()?;
+    let mut meta = write_sentence_file(&sent_ngram, 0, "prim", PRIM_LANGUAGE, prim_gram.grams.len(), token_width, fingerprint)?;
 
     println!("Writing secondary sentences");
-    let mut sec_output = BufWriter::new(File::create(get_cache_path("sentences-sec.bin"))?);
-    let sec_meta = sent_ngram.write_sentences(&mut sec_output, 1)?;
-    sec_output.flush()?;
-
+    let sec_meta = write_sentence_file(&sent_ngram, 1, "sec", SEC_LANGUAGE, sec_gram.grams.len(), token_width, fingerprint)?;
     meta.extend(sec_meta.into_iter());
 
     println!("Writing auxiliary sentences");
-    let mut aux_output = BufWriter::new(File::create(get_cache_path("sentences-aux.bin"))?);
-    let aux_meta = sent_ngram.write_sentences(&mut aux_output, 2)?;
-    sec_output.flush()?;
-
+    let aux_meta = write_sentence_file(&sent_ngram, 2, "aux", AUX_LANGUAGE, aux_gram.grams.len(), token_width, fingerprint)?;
     meta.extend(aux_meta.into_iter());
 
-    println!("Writing secondary links");
-    let mut links_output = BufWriter::new(File::create(get_cache_path("sec-links.bin"))?);
-    sent_ngram.write_links(&mut links_output, &meta, true)?;
-    links_output.flush()?;
+    let width = link_width(&meta);
+    println!("Writing links with {}-bit offsets", width * 8);
+
+    println!("Writing sentence IDs");
+    for &(from, name, language) in &[(0, "prim", PRIM_LANGUAGE), (1, "sec", SEC_LANGUAGE), (2, "aux", AUX_LANGUAGE)] {
+        let ids = sent_ngram.sentence_ids(from);
+        let mut ids_output = create_artifact(&format!("ids-{}.bin", name), Kind::Ids, 4, language, ids.len(), fingerprint)?;
+        for id in ids {
+            ids_output.write_all(&id.to_le_bytes())?;
+        }
+        ids_output.flush()?;
+    }
+
+    // Where each sentence is in its language's sentence file, counted in sentences. Tatoeba's IDs are unique across languages.
+    let mut sentence_index = HashMap::new();
+    for &from in &[0, 1, 2] {
+        sentence_index.extend(sent_ngram.sentence_ids(from).into_iter().enumerate().map(|(i, id)| (id, i as u32)));
+    }
+
+    if WRITE_NPY {
+        println!("Writing NumPy arrays");
+        std::fs::create_dir_all(get_cache_path(NPY_DIR))?;
+        for &(from, name) in &[(0, "prim"), (1, "sec"), (2, "aux")] {
+            let (tokens, offsets) = sent_ngram.token_stream(from);
+            write_npy_tokens(&format!("tokens-{}.npy", name), &tokens, token_width)?;
+
+            let mut offsets_output = BufWriter::new(File::create(get_cache_path(&format!("{}/offsets-{}.npy", NPY_DIR, name)))?);
+            npy::write_npy(&mut offsets_output, &[offsets.len()], &offsets)?;
+            offsets_output.flush()?;
+        }
+    }
+
+    let mut build_info = vec![
+        ("fingerprint".to_string(), format!("{:016x}", fingerprint)),
+        ("format_version".to_string(), format::VERSION.to_string()),
+        ("languages".to_string(), format!("{} {} {}", PRIM_LANGUAGE, SEC_LANGUAGE, AUX_LANGUAGE)),
+        ("token_width".to_string(), token_width.to_string()),
+        ("link_width".to_string(), width.to_string()),
+    ];
+    for &(from, name, grams) in &[(0, "prim", &prim_gram.grams), (1, "sec", &sec_gram.grams), (2, "aux", &aux_gram.grams)] {
+        build_info.push((format!("{}_grams", name), grams.len().to_string()));
+        build_info.push((format!("{}_sentences", name), sent_ngram.sentence_ids(from).len().to_string()));
+    }
+
+
+    let sec_pair = format!("{}-{}", PRIM_LANGUAGE, SEC_LANGUAGE);
+    let aux_pair = format!("{}-{}", PRIM_LANGUAGE, AUX_LANGUAGE);
+
+    // Gram rarity for the curriculum only comes from train sentences, so that no dev or test data leaks into it
+    let prim_surprisal = curriculum::gram_surprisal(train_sentences(&sent_ngram.prim_language, &splits), prim_gram.grams.len());
+    let sec_surprisal = curriculum::gram_surprisal(train_sentences(&sent_ngram.sec_language, &splits), sec_gram.grams.len());
+    let aux_surprisal = curriculum::gram_surprisal(train_sentences(&sent_ngram.aux_language, &splits), aux_gram.grams.len());
+
+    let mut jsonl_output = if WRITE_JSONL { Some(BufWriter::new(File::create(get_cache_path(JSONL_FILE))?)) } else { None };
+
+    let mut arrow_outputs = Vec::new();
+    if WRITE_ARROW {
+        std::fs::create_dir_all(get_cache_path(ARROW_DIR))?;
+        for pair in &[&sec_pair, &aux_pair] {
+            let output = BufWriter::new(File::create(get_cache_path(&format!("{}/{}.arrow", ARROW_DIR, pair)))?);
+            arrow_outputs.push(arrow::Writer::new(output, arrow_fields())?);
+        }
+    }
+
+    for &split in &Split::ALL {
+        let mut sec_links = sent_ngram.split_links(true, &splits, split);
+        let mut aux_links = sent_ngram.split_links(false, &splits, split);
+        sent_ngram.sort_links_by_length(&mut sec_links);
+        sent_ngram.sort_links_by_length(&mut aux_links);
+        println!("Writing {} links ({} secondary, {} auxiliary)", split.name(), sec_links.len(), aux_links.len());
+        build_info.push((format!("sec_links_{}", split.name()), sec_links.len().to_string()));
+        build_info.push((format!("aux_links_{}", split.name()), aux_links.len().to_string()));
+
+        let mut links_output = create_artifact(&format!("sec-links-{}.bin", split.name()), Kind::Links, width, &sec_pair, sec_links.len(), fingerprint)?;
+        write_links(&mut links_output, &sec_links, &meta, width)?;
+        links_output.flush()?;
+
+        let mut links_output = create_artifact(&format!("aux-links-{}.bin", split.name()), Kind::Links, width, &aux_pair, aux_links.len(), fingerprint)?;
+        write_links(&mut links_output, &aux_links, &meta, width)?;
+        links_output.flush()?;
+
+        if let (Some(raw_text), Some(jsonl_output)) = (raw_text.as_ref(), jsonl_output.as_mut()) {
+            for &(links, language, texts, sentences) in &[
+                (&sec_links, SEC_LANGUAGE, &raw_text.sec_language, &sent_ngram.sec_language),
+                (&aux_links, AUX_LANGUAGE, &raw_text.aux_language, &sent_ngram.aux_language),
+            ] {
+                for &(prim_id, other_id) in links.iter() {
+                    let prim = jsonl::Side { id: prim_id, language: PRIM_LANGUAGE, text: &raw_text.prim_language[&prim_id], tokens: &sent_ngram.prim_language[&prim_id] };
+                    let other = jsonl::Side { id: other_id, language, text: &texts[&other_id], tokens: &sentences[&other_id] };
+                    jsonl::write_pair(jsonl_output, split.name(), &prim, &other)?;
+                }
+            }
+        }
 
-    println!("Writing auxiliary links");
-    let mut links_output = BufWriter::new(File::create(get_cache_path("aux-links.bin"))?);
-    sent_ngram.write_links(&mut links_output, &meta, false)?;
-    links_output.flush()?;
+        if let Some(raw_text) = raw_text.as_ref() {
+            for (arrow_output, &(links, texts, sentences)) in arrow_outputs.iter_mut().zip(&[
+                (&sec_links, &raw_text.sec_language, &sent_ngram.sec_language),
+                (&aux_links, &raw_text.aux_language, &sent_ngram.aux_language),
+            ]) {
+                let columns = [
+                    arrow::Column::Utf8(vec![split.name(); links.len()]),
+                    arrow::Column::UInt32(links.iter().map(|&(prim_id, _)| prim_id).collect()),
+                    arrow::Column::UInt32(links.iter().map(|&(_, other_id)| other_id).collect()),
+                    arrow::Column::Utf8(links.iter().map(|(prim_id, _)| raw_text.prim_language[prim_id].as_str()).collect()),
+                    arrow::Column::Utf8(links.iter().map(|(_, other_id)| texts[other_id].as_str()).collect()),
+                    arrow::Column::ListUInt32(links.iter().map(|(prim_id, _)| sent_ngram.prim_language[prim_id].as_slice()).collect()),
+                    arrow::Column::ListUInt32(links.iter().map(|(_, other_id)| sentences[other_id].as_slice()).collect()),
+                ];
+                arrow_output.write_batch(links.len(), &columns)?;
+            }
+        }
+
+        if WRITE_NPY {
+            write_npy_links(&format!("sec-links-{}.npy", split.name()), &sec_links, &sentence_index)?;
+            write_npy_links(&format!("aux-links-{}.npy", split.name()), &aux_links, &sentence_index)?;
+        }
+
+        let sec_lengths: Vec<usize> = sec_links.iter().map(|&link| sent_ngram.link_length(link)).collect();
+        let aux_lengths: Vec<usize> = aux_links.iter().map(|&link| sent_ngram.link_length(link)).collect();
+        let sec_buckets = sampling::bucket_by_length(&sec_lengths, BUCKET_WIDTH);
+        let aux_buckets = sampling::bucket_by_length(&aux_lengths, BUCKET_WIDTH);
+
+        let mut buckets_output = create_artifact(&format!("sec-buckets-{}.bin", split.name()), Kind::Buckets, 4, &sec_pair, sec_buckets.len(), fingerprint)?;
+        sampling::write_buckets(&mut buckets_output, &sec_buckets)?;
+        buckets_output.flush()?;
+
+        let mut buckets_output = create_artifact(&format!("aux-buckets-{}.bin", split.name()), Kind::Buckets, 4, &aux_pair, aux_buckets.len(), fingerprint)?;
+        sampling::write_buckets(&mut buckets_output, &aux_buckets)?;
+        buckets_output.flush()?;
+
+        if split == Split::Train {
+            let task_probs = sampling::task_probabilities(&[sec_links.len(), aux_links.len()], TASK_TEMPERATURE);
+            println!(
+                "Sampling secondary/auxiliary links {:.3}/{:.3} (secondary upsampled {:.2} times)",
+                task_probs[0], task_probs[1],
+                task_probs[0] * (sec_links.len() + aux_links.len()) as f64 / sec_links.len() as f64,
+            );
+
+            let sec_weights: Vec<f64> = sampling::pair_weights(&sec_links, BALANCE_PRIM_SENTENCES).into_iter().map(|w| w * task_probs[0]).collect();
+            let aux_weights: Vec<f64> = sampling::pair_weights(&aux_links, BALANCE_PRIM_SENTENCES).into_iter().map(|w| w * task_probs[1]).collect();
+
+            let mut weights_output = create_artifact("sec-weights.bin", Kind::Weights, 4, &sec_pair, sec_weights.len(), fingerprint)?;
+            sampling::write_weights(&mut weights_output, &sec_weights)?;
+            weights_output.flush()?;
+
+            let mut weights_output = create_artifact("aux-weights.bin", Kind::Weights, 4, &aux_pair, aux_weights.len(), fingerprint)?;
+            sampling::write_weights(&mut weights_output, &aux_weights)?;
+            weights_output.flush()?;
+
+            if let Some(n_folds) = N_FOLDS {
+                let folds = splits::assign_folds(&sent_ngram.links, n_folds, FOLD_KEY);
+                let sec_folds: Vec<u8> =
+                    sec_links
+                    .iter()
+                    .map(|(prim_id, _)| u8::try_from(folds[prim_id]).map_err(|_| Error::new(ErrorKind::InvalidData, format!("fold {} does not fit in a byte", folds[prim_id]))))
+                    .collect::<Result<_>>()?;
+                for fold in 0..n_folds {
+                    println!("Fold {}: {} secondary links", fold, sec_folds.iter().filter(|&&f| f as usize == fold).count());
+                }
+
+                let mut folds_output = create_artifact("sec-folds.bin", Kind::Folds, 1, &sec_pair, sec_folds.len(), fingerprint)?;
+                folds_output.write_all(&sec_folds)?;
+                folds_output.flush()?;
+            }
+
+            println!("Writing curriculum");
+            let sec_scores = curriculum::difficulty(
+                &sec_links, &sent_ngram.prim_language, &sent_ngram.sec_language,
+                &prim_surprisal, &sec_surprisal, &CURRICULUM_WEIGHTS,
+            );
+            let aux_scores = curriculum::difficulty(
+                &aux_links, &sent_ngram.prim_language, &sent_ngram.aux_language,
+                &prim_surprisal, &aux_surprisal, &CURRICULUM_WEIGHTS,
+            );
+
+            for &(name, pair, links, scores) in &[("sec", &sec_pair, &sec_links, &sec_scores), ("aux", &aux_pair, &aux_links, &aux_scores)] {
+                let (sorted_links, sorted_scores) = curriculum::sort_by_difficulty(links, scores);
+
+                let mut curriculum_output = create_artifact(&format!("{}-curriculum.bin", name), Kind::Links, width, pair, sorted_links.len(), fingerprint)?;
+                write_links(&mut curriculum_output, &sorted_links, &meta, width)?;
+                curriculum_output.flush()?;
+
+                let mut scores_output = create_artifact(&format!("{}-curriculum-scores.bin", name), Kind::Weights, 4, pair, sorted_scores.len(), fingerprint)?;
+                sampling::write_weights(&mut scores_output, &sorted_scores)?;
+                scores_output.flush()?;
+            }
+        }
+    }
+
+    if let Some(mut jsonl_output) = jsonl_output {
+        jsonl_output.flush()?;
+    }
+    for arrow_output in arrow_outputs {
+        arrow_output.finish()?.flush()?;
+    }
+
+    write_epoch_permutations(N_EPOCHS)?;
+
+    let mut build_info_output = BufWriter::new(File::create(get_cache_path(BUILD_INFO_FILE))?);
+    for (key, value) in &build_info {
+        writeln!(build_info_output, "{}\t{}", key, value)?;
+    }
+    build_info_output.flush()?;
+
+    if WRITE_CONTAINER {
+        pack(&get_cache_path(CONTAINER_FILE))?;
+    }
+
+    write_manifest(fingerprint, &build_info)?;
 
     println!("Done!");
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Writes a small Tatoeba dump of random sentences from a few words per language, each English sentence linked
+    // in both directions to up to one toki pona and two Spanish translations
+    fn write_raw_data(seed: u64) -> Result<()> {
+        let words: [(&str, &[&str]); 3] = [
+            (PRIM_LANGUAGE, &["i", "you", "the", "cat", "dog", "eat", "water", "see", "big", "good", "house"]),
+            (SEC_LANGUAGE, &["mi", "sina", "soweli", "moku", "telo", "lukin", "suli", "pona", "tomo", "li", "e"]),
+            (AUX_LANGUAGE, &["yo", "tu", "el", "gato", "perro", "comer", "agua", "ver", "grande", "bueno", "casa"]),
+        ];
+        let mut rng = rng::Rng::new(seed);
+        let sentence = |rng: &mut rng::Rng, language: usize, n_words: usize| -> String {
+            let words = words[language].1;
+            (0..n_words).map(|_| words[rng.below(words.len())]).collect::<Vec<_>>().join(" ") + "."
+        };
+
+        let mut sentences = BufWriter::new(File::create(get_cache_path("raw/sentences.tsv"))?);
+        let mut links = BufWriter::new(File::create(get_cache_path("raw/links.tsv"))?);
+        let mut id = 1;
+        for _ in 0..300 {
+            let n_words = 2 + rng.below(8);
+            let prim_id = id;
+            writeln!(sentences, "{}\t{}\t{}", prim_id, words[0].0, sentence(&mut rng, 0, n_words))?;
+            id += 1;
+
+            let n_aux = rng.below(3);
+            let translations = (rng.below(3) == 0).then_some(1).into_iter().chain(std::iter::repeat_n(2, n_aux));
+            for language in translations {
+                writeln!(sentences, "{}\t{}\t{}", id, words[language].0, sentence(&mut rng, language, n_words))?;
+                writeln!(links, "{}\t{}", prim_id, id)?;
+                writeln!(links, "{}\t{}", id, prim_id)?;
+                id += 1;
+            }
+        }
+        sentences.flush()?;
+        links.flush()
+    }
+
+    // A temporary cache directory for the current thread, which is removed again when the test ends or fails
+    struct TempCache {
+        dir: std::path::PathBuf,
+    }
+
+    impl TempCache {
+        fn new(name: &str) -> Result<TempCache> {
+            let dir = std::env::temp_dir().join(format!("select-langs-{}-{}", name, std::process::id()));
+            std::fs::create_dir_all(dir.join("raw"))?;
+            CACHE_DIR.with(|cache_dir| *cache_dir.borrow_mut() = dir.to_string_lossy().into_owned());
+            Ok(TempCache { dir })
+        }
+    }
+
+    impl Drop for TempCache {
+        fn drop(&mut self) {
+            CACHE_DIR.with(|cache_dir| *cache_dir.borrow_mut() = "cache".to_string());
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    #[test]
+    fn permute_keeps_the_build_verified() -> Result<()> {
+        let _cache = TempCache::new("permute")?;
+
+        write_raw_data(1)?;
+        build()?;
+        verify_build()?;
+        permute(N_EPOCHS + 3)?;
+        verify_build()
+    }
+}
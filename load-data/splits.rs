@@ -0,0 +1,184 @@
+// Splits the corpus into train, dev and test sets without leaking sentences between them.
+// Sentences connected through links form a component: an English sentence, all its toki pona and Spanish translations,
+// and through those any other English sentence with the same translation. A whole component always goes into the
+// same split, so that the encoder never sees a dev or test sentence, not even through the auxiliary task.
+
+// The split of a component is decided by a keyed hash of its smallest sentence ID, so that rebuilding from a newer
+// Tatoeba dump gives the same splits. Since new links can join components, or remove the smallest sentence, the
+// splits of the previous build are also kept in a file. A component with sentences from the previous build keeps
+// their split, and new sentences join it. Only when a new link joins two components of different splits, the stricter
+// split (test, then dev) wins for the whole component, so that no evaluation sentence ever moves into train, though
+// train sentences can move out of it.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{Result, Error, ErrorKind, BufReader, BufRead, Write};
+use std::fs::File;
+
+use crate::hash::keyed_unit;
+
+// Ordered from least to most strict
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Split {
+    Train,
+    Dev,
+    Test,
+}
+
+impl Split {
+    pub const ALL: [Split; 3] = [Split::Train, Split::Dev, Split::Test];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Split::Train => "train",
+            Split::Dev => "dev",
+            Split::Test => "test",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Split> {
+        Split::ALL.iter().cloned().find(|split| split.name() == name)
+    }
+}
+
+struct UnionFind {
+    parent: HashMap<u32, u32>,
+}
+
+impl UnionFind {
+    fn find(&mut self, id: u32) -> u32 {
+        let parent = *self.parent.entry(id).or_insert(id);
+        if parent == id {
+            return id;
+        }
+
+        let root = self.find(parent);
+        self.parent.insert(id, root);
+        root
+    }
+
+    // The smaller root becomes the root of the union, so every component ends up rooted in its smallest ID
+    fn union(&mut self, a: u32, b: u32) {
+        let (root_a, root_b) = (self.find(a), self.find(b));
+        if root_a < root_b {
+            self.parent.insert(root_b, root_a);
+        } else if root_b < root_a {
+            self.parent.insert(root_a, root_b);
+        }
+    }
+}
+
+// Maps every linked sentence ID to the smallest ID in its component
+pub fn components(links: &HashSet<(u32, u32)>) -> HashMap<u32, u32> {
+    let mut union_find = UnionFind { parent: HashMap::new() };
+    for &(a, b) in links {
+        union_find.union(a, b);
+    }
+
+    let ids: Vec<u32> = union_find.parent.keys().cloned().collect();
+    ids.into_iter().map(|id| (id, union_find.find(id))).collect()
+}
+
+fn hashed_split(root: u32, dev_fraction: f64, test_fraction: f64, key: u64) -> Split {
+    let r = keyed_unit(key, root);
+    if r < test_fraction {
+        Split::Test
+    } else if r < test_fraction + dev_fraction {
+        Split::Dev
+    } else {
+        Split::Train
+    }
+}
+
+// Puts each component into a split, and returns the split of every linked sentence.
+// A component with sentences from the previous build gets the strictest of their splits, and only components of new
+// sentences are hashed, since the smallest ID of a component can change between builds.
+// The fractions are of the number of components, not sentences.
+pub fn assign_splits(
+    links: &HashSet<(u32, u32)>,
+    dev_fraction: f64, test_fraction: f64, key: u64,
+    previous: &HashMap<u32, Split>,
+) -> HashMap<u32, Split> {
+    let components = components(links);
+
+    let mut root_splits: HashMap<u32, Split> = HashMap::new();
+    for (id, root) in &components {
+        if let Some(&split) = previous.get(id) {
+            let root_split = root_splits.entry(*root).or_insert(split);
+            *root_split = (*root_split).max(split);
+        }
+    }
+
+    components
+        .into_iter()
+        .map(|(id, root)| {
+            let split = *root_splits.entry(root).or_insert_with(|| hashed_split(root, dev_fraction, test_fraction, key));
+            (id, split)
+        })
+        .collect()
+}
+
+// One line per sentence: sentence ID, split. A missing file gives no splits.
+pub fn read_splits(path: &str) -> Result<HashMap<u32, Split>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == ErrorKind::NotFound => return Ok(HashMap::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut splits = HashMap::new();
+    for (line_nr, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        let invalid = || Error::new(ErrorKind::InvalidData, format!("{}:{}: expected sentence ID and split", path, line_nr + 1));
+
+        let mut parts = line.split('\t');
+        let id = parts.next().and_then(|id| id.parse().ok()).ok_or_else(invalid)?;
+        let split = parts.next().and_then(Split::from_name).ok_or_else(invalid)?;
+        splits.insert(id, split);
+    }
+
+    Ok(splits)
+}
+
+pub fn write_splits<F: Write>(file: &mut F, splits: &HashMap<u32, Split>) -> Result<()> {
+    let mut ids: Vec<u32> = splits.keys().cloned().collect();
+    ids.sort();
+
+    for id in ids {
+        writeln!(file, "{}\t{}", id, splits[&id].name())?;
+    }
+    Ok(())
+}
+
+// Puts each component into one of n_folds folds for cross-validation, by the same kind of keyed hash as the splits.
+// Returns the fold of every linked sentence. n_folds must be at least 1.
+pub fn assign_folds(links: &HashSet<(u32, u32)>, n_folds: usize, key: u64) -> HashMap<u32, usize> {
+    components(links)
+        .into_iter()
+        .map(|(id, root)| (id, ((keyed_unit(key, root) * n_folds as f64) as usize).min(n_folds - 1)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sentences_keep_their_previous_split() {
+        let previous: HashMap<u32, Split> = [
+            // Sentence 1 was the smallest of its component, but has been removed
+            (1, Split::Train), (7, Split::Train), (10, Split::Train), (11, Split::Train),
+            // Two components in different splits
+            (30, Split::Train), (31, Split::Dev),
+        ].iter().cloned().collect();
+        // 12 and the component of 20 and 21 are new, and a new link joins 30 and 31
+        let links: HashSet<(u32, u32)> = [(7, 10), (7, 11), (7, 12), (20, 21), (30, 31)].iter().cloned().collect();
+
+        // Everything which is hashed goes into test
+        let splits = assign_splits(&links, 0.0, 1.0, 0, &previous);
+        for id in &[7, 10, 11, 12] {
+            assert_eq!(splits[id], Split::Train, "sentence {}", id);
+        }
+        assert_eq!((splits[&20], splits[&21]), (Split::Test, Split::Test));
+        assert_eq!((splits[&30], splits[&31]), (Split::Dev, Split::Dev));
+    }
+}
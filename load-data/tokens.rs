@@ -1,8 +1,10 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, BinaryHeap};
+use std::collections::hash_map::Entry;
+use std::cmp::Reverse;
 use std::hash::Hash;
 use std::fmt::Debug;
 
-use std::io::Write;
+use std::io::{Read, Write};
 
 #[derive(Debug, Copy, Clone)]
 pub enum Gram<I> {
@@ -11,7 +13,16 @@ pub enum Gram<I> {
 }
 
 // Retuns the tokenized text, along with a list of decompositions
-pub fn encode_into_ngrams<I: Debug + Copy + Eq + Hash, F: Fn(&I) -> bool>(inp: Vec<I>, rel_lim: f64, can_pair: F) -> (Vec<usize>, Vec<Gram<I>>) {
+// The reserved items always get the first orig grams, in order, even if they don't occur in the input
+// Merging stops once there are max_grams grams in total
+// Each merge takes the commonest pair, with ties going to the smaller pair so that the same input always gives the
+// same grams, and contracts its occurrences from left to right. The orig grams are numbered by their first occurrence,
+// so the order of the input matters too.
+
+// Instead of recounting the pairs for every merge, the counts are kept up to date: the tokens are a linked list, and
+// each pair remembers where it occurs, so a merge only touches its occurrences and their neighbours. The commonest
+// pair comes from a heap, which may hold outdated counts; those are skipped when they come up.
+pub fn encode_into_ngrams<I: Debug + Copy + Eq + Hash, F: Fn(&I) -> bool>(inp: Vec<I>, rel_lim: f64, max_grams: usize, reserved: &[I], can_pair: F) -> (Vec<usize>, Vec<Gram<I>>) {
     // Convert the text into orig tokens
 
     let inp_len = inp.len() as f64;
@@ -22,6 +33,18 @@ pub fn encode_into_ngrams<I: Debug + Copy + Eq + Hash, F: Fn(&I) -> bool>(inp: V
     let mut skips = HashSet::new();
 
     let mut i2tok: HashMap<I, usize> = HashMap::new();
+    for &i in reserved {
+        if let Entry::Vacant(entry) = i2tok.entry(i) {
+            let idx = grams.len();
+            entry.insert(idx);
+            grams.push(Gram::Orig(i));
+
+            if !can_pair(&i) {
+                skips.insert(idx);
+            }
+        }
+    }
+
     for i in inp {
         if !i2tok.contains_key(&i) {
             let idx = grams.len();
@@ -39,13 +62,14 @@ pub fn encode_into_ngrams<I: Debug + Copy + Eq + Hash, F: Fn(&I) -> bool>(inp: V
     // println!("Tokens: {:?}", tokens);
     // println!("Grams: {:?}", grams);
 
-    for _ in 0..std::u16::MAX - tokens.len() as u16 {
-        let pair_freq = get_pair_freq(&tokens, &skips);
-        let (&commonest_pair, &freq) = if let Some(x) = pair_freq.iter().max_by_key(|(_, &count)| count) {
+    let mut pairs = PairCounter::new(tokens, skips);
+
+    while grams.len() < max_grams {
+        let (commonest_pair, freq) = if let Some(x) = pairs.commonest() {
             x
         } else {
-            eprintln!("Ran out of pairs: {:?} / {:?} - {:?}", tokens, grams, skips);
-            return (tokens, grams);
+            eprintln!("Ran out of pairs: {:?} / {:?} - {:?}", pairs.tokens(), grams, pairs.skips);
+            return (pairs.tokens(), grams);
         };
         let rel_freq = freq as f64 / inp_len as f64;
         println!("Commonest: {:?}, freq {}, {}%", commonest_pair, freq, rel_freq);
@@ -56,46 +80,141 @@ pub fn encode_into_ngrams<I: Debug + Copy + Eq + Hash, F: Fn(&I) -> bool>(inp: V
         let new_gram_idx = grams.len();
         grams.push(Gram::Composition(commonest_pair.0, commonest_pair.1));
 
-        let mut contracted_tokens = Vec::with_capacity(tokens.len() - freq);
-        let mut at = 0;
-        while at < tokens.len() {
-            let here = tokens[at];
-            let next = tokens.get(at + 1);
+        pairs.merge(commonest_pair, new_gram_idx);
 
-            if here == commonest_pair.0 && next == Some(&commonest_pair.1) {
-                contracted_tokens.push(new_gram_idx);
-                at += 2;
-            } else {
-                contracted_tokens.push(here);
-                at += 1;
-            }
+        // println!("Tokens: {:?}", pairs.tokens());
+        // println!("Grams: {:?}", grams);
+    }
+
+    (pairs.tokens(), grams)
+}
+
+// Marks a token which has been merged into the one before it
+const MERGED: usize = usize::MAX;
+// No previous or next token
+const NONE: usize = usize::MAX;
+
+// The counts of all adjacent pairs of tokens which don't involve a skip, where they occur, and the tokens themselves
+struct PairCounter {
+    tokens: Vec<usize>,
+    prev: Vec<usize>,
+    next: Vec<usize>,
+    skips: HashSet<usize>,
+
+    counts: HashMap<(usize, usize), usize>,
+    // The position of the first token of each occurrence, plus positions where the pair no longer occurs
+    positions: HashMap<(usize, usize), Vec<usize>>,
+    // Counts paired with the reversed pair, so that the heap's maximum is the commonest and then the smallest pair
+    heap: BinaryHeap<(usize, Reverse<(usize, usize)>)>,
+}
+
+impl PairCounter {
+    fn new(tokens: Vec<usize>, skips: HashSet<usize>) -> PairCounter {
+        let n = tokens.len();
+        let mut counter = PairCounter {
+            tokens,
+            prev: (0..n).map(|i| if i == 0 { NONE } else { i - 1 }).collect(),
+            next: (0..n).map(|i| if i + 1 == n { NONE } else { i + 1 }).collect(),
+            skips,
+            counts: HashMap::new(),
+            positions: HashMap::new(),
+            heap: BinaryHeap::new(),
+        };
+
+        for at in 0..n.saturating_sub(1) {
+            let pair = (counter.tokens[at], counter.tokens[at + 1]);
+            counter.add(pair, at);
         }
+        let heap = counter.counts.iter().map(|(&pair, &count)| (count, Reverse(pair))).collect();
+        counter.heap = heap;
 
-        tokens = contracted_tokens;
+        counter
+    }
 
-        // println!("Tokens: {:?}", tokens);
-        // println!("Grams: {:?}", grams);
+    fn counts_pair(&self, pair: (usize, usize)) -> bool {
+        !self.skips.contains(&pair.0) && !self.skips.contains(&pair.1) // Don't combine over word boundaries
     }
 
-    (tokens, grams)
-}
+    fn add(&mut self, pair: (usize, usize), at: usize) {
+        if self.counts_pair(pair) {
+            *self.counts.entry(pair).or_insert(0) += 1;
+            self.positions.entry(pair).or_default().push(at);
+        }
+    }
 
-fn get_pair_freq(input: &[usize], skips: &HashSet<usize>) -> HashMap<(usize, usize), usize> {
-    let mut counter = HashMap::new();
+    fn remove(&mut self, pair: (usize, usize)) {
+        if self.counts_pair(pair) {
+            let count = self.counts.get_mut(&pair).expect("pair to remove was never counted");
+            *count -= 1;
+            if *count == 0 {
+                self.counts.remove(&pair);
+                self.positions.remove(&pair);
+            }
+        }
+    }
+
+    fn commonest(&mut self) -> Option<((usize, usize), usize)> {
+        while let Some(&(count, Reverse(pair))) = self.heap.peek() {
+            // Drop entries whose count has changed since they were pushed
+            if self.counts.get(&pair) == Some(&count) {
+                return Some((pair, count));
+            }
+            self.heap.pop();
+        }
+        None
+    }
+
+    // Contracts every occurrence of pair from left to right into new_gram, like a single pass over the tokens would
+    fn merge(&mut self, pair: (usize, usize), new_gram: usize) {
+        let mut positions = self.positions.remove(&pair).unwrap_or_default();
+        positions.sort_unstable();
+        positions.dedup();
+
+        let mut changed = HashSet::new();
+        for at in positions {
+            // The pair may have moved on, or its first token may have just been merged into the one before it
+            let second = self.next[at];
+            if self.tokens[at] != pair.0 || second == NONE || self.tokens[second] != pair.1 {
+                continue;
+            }
+            let before = self.prev[at];
+            let after = self.next[second];
+
+            if before != NONE {
+                let neighbour = self.tokens[before];
+                self.remove((neighbour, pair.0));
+                self.add((neighbour, new_gram), before);
+                changed.insert((neighbour, pair.0));
+                changed.insert((neighbour, new_gram));
+            }
+            if after != NONE {
+                let neighbour = self.tokens[after];
+                self.remove((pair.1, neighbour));
+                self.add((new_gram, neighbour), at);
+                changed.insert((pair.1, neighbour));
+                changed.insert((new_gram, neighbour));
+                self.prev[after] = at;
+            }
+            self.remove(pair);
 
-    for (&a, &b) in input.iter().zip(input.iter().skip(1)) {
-        if skips.contains(&a) || skips.contains(&b) {
-            continue; // Don't combine over word boundaries
+            self.tokens[at] = new_gram;
+            self.tokens[second] = MERGED;
+            self.next[at] = after;
         }
 
-        let count = counter.entry((a, b)).or_insert(0);
-        *count += 1;
+        for pair in changed {
+            if let Some(&count) = self.counts.get(&pair) {
+                self.heap.push((count, Reverse(pair)));
+            }
+        }
     }
 
-    counter
+    fn tokens(&self) -> Vec<usize> {
+        self.tokens.iter().cloned().filter(|&token| token != MERGED).collect()
+    }
 }
 
-fn decompose_sequence<I: Eq + Hash + Copy>(mut tokens: Vec<usize>, grams: &[Gram<I>]) -> Vec<I> {
+pub fn decompose_sequence<I: Eq + Hash + Copy>(mut tokens: Vec<usize>, grams: &[Gram<I>]) -> Vec<I> {
     // Make all tokens point into Orig-ngrams
     while {
         let mut new_tokens = Vec::new();
@@ -140,7 +259,7 @@ fn decompose_sequence<I: Eq + Hash + Copy>(mut tokens: Vec<usize>, grams: &[Gram
 // offset: 0x0 0x1 0x2 0x3 0x4 0x5 0x6 0x7 0x8
 //  value: 0x0 -------a------- -------b-------
 
-pub fn encode_grams<F: Write>(out: &mut F, grams: Vec<Gram<char>>) -> std::io::Result<()> {
+pub fn encode_grams<F: Write>(out: &mut F, grams: &[Gram<char>]) -> std::io::Result<()> {
     // Assert topoorder
     for (i, gram) in grams.iter().enumerate() {
         match gram {
@@ -149,7 +268,7 @@ pub fn encode_grams<F: Write>(out: &mut F, grams: Vec<Gram<char>>) -> std::io::R
         }
     }
 
-    for gram in grams {
+    for &gram in grams {
         match gram {
             Gram::Orig(ch) => {
                 let chl = ch.len_utf8();
@@ -173,10 +292,38 @@ pub fn encode_grams<F: Write>(out: &mut F, grams: Vec<Gram<char>>) -> std::io::R
     Ok(())
 }
 
+// Reads back n grams written by encode_grams
+pub fn decode_grams<R: Read>(inp: &mut R, n: usize) -> std::io::Result<Vec<Gram<char>>> {
+    let invalid = |msg: String| std::io::Error::new(std::io::ErrorKind::InvalidData, msg);
+
+    let mut grams = Vec::with_capacity(n);
+    for i in 0..n {
+        let mut buf = [0u8; 9];
+        inp.read_exact(&mut buf)?;
+
+        match buf[0] {
+            0 => {
+                let a = u32::from_le_bytes([buf[1], buf[2], buf[3], buf[4]]) as usize;
+                let b = u32::from_le_bytes([buf[5], buf[6], buf[7], buf[8]]) as usize;
+                grams.push(Gram::Composition(a, b));
+            }
+            chl @ 1..=4 => {
+                let ch =
+                    std::str::from_utf8(&buf[1..1 + chl as usize])
+                    .ok()
+                    .and_then(|s| s.chars().next())
+                    .ok_or_else(|| invalid(format!("gram {} is not valid utf-8", i)))?;
+                grams.push(Gram::Orig(ch));
+            }
+            tag => return Err(invalid(format!("gram {} has invalid tag {}", i, tag))),
+        }
+    }
+
+    Ok(grams)
+}
+
 #[allow(unused)]
 fn main() -> std::io::Result<()> {
-    use std::io::Read;
-
     let mut f = std::fs::File::open("cache/sentences-sec.txt")?;
     let mut buf = Vec::new();
     f.read_to_end(&mut buf)?;
@@ -184,7 +331,7 @@ fn main() -> std::io::Result<()> {
     let inp = String::from_utf8_lossy(&buf).chars().collect();
 
     println!("Encoding");
-    let (stream, grams) = encode_into_ngrams(inp, 0.001, |&_ch| true);
+    let (stream, grams) = encode_into_ngrams(inp, 0.001, u16::MAX as usize, &[], |&_ch| true);
 
     // println!("{:?}", decompose_sequence(stream, &grams));
     for i in 0..grams.len() {
@@ -198,7 +345,88 @@ fn main() -> std::io::Result<()> {
     }
 
     let mut out = std::fs::File::create("/tmp/gramcode.bin")?;
-    encode_grams(&mut out, grams)?;
+    encode_grams(&mut out, &grams)?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The algorithm before the pair counts were kept up to date: all pairs are recounted before every merge
+    fn recounting_encode(inp: Vec<char>, rel_lim: f64, max_grams: usize, reserved: &[char], can_pair: fn(&char) -> bool) -> (Vec<usize>, Vec<Gram<char>>) {
+        let inp_len = inp.len() as f64;
+
+        let mut grams = Vec::new();
+        let mut i2tok = HashMap::new();
+        let mut orig = |i: char| *i2tok.entry(i).or_insert_with(|| {
+            grams.push(Gram::Orig(i));
+            grams.len() - 1
+        });
+        for &i in reserved {
+            orig(i);
+        }
+        let mut tokens: Vec<usize> = inp.iter().map(|&i| orig(i)).collect();
+        let skips: HashSet<usize> = i2tok.iter().filter(|&(i, _)| !can_pair(i)).map(|(_, &idx)| idx).collect();
+
+        while grams.len() < max_grams {
+            let mut counts: HashMap<(usize, usize), usize> = HashMap::new();
+            for pair in tokens.windows(2) {
+                if !skips.contains(&pair[0]) && !skips.contains(&pair[1]) {
+                    *counts.entry((pair[0], pair[1])).or_insert(0) += 1;
+                }
+            }
+
+            // The commonest pair, and the smallest of those
+            let (pair, count) = match counts.into_iter().max_by_key(|&(pair, count)| (count, Reverse(pair))) {
+                Some(best) => best,
+                None => break,
+            };
+            if (count as f64 / inp_len) < rel_lim {
+                break;
+            }
+
+            let new_gram = grams.len();
+            grams.push(Gram::Composition(pair.0, pair.1));
+
+            let mut merged = Vec::new();
+            let mut at = 0;
+            while at < tokens.len() {
+                if tokens[at] == pair.0 && tokens.get(at + 1) == Some(&pair.1) {
+                    merged.push(new_gram);
+                    at += 2;
+                } else {
+                    merged.push(tokens[at]);
+                    at += 1;
+                }
+            }
+            tokens = merged;
+        }
+
+        (tokens, grams)
+    }
+
+    #[test]
+    fn merges_like_recounting_every_pair() {
+        let alphabet: Vec<char> = "aaabbc de\0".chars().collect();
+        let can_pair: fn(&char) -> bool = |&ch| ch != '\0' && ch != ' ';
+
+        let mut state: u64 = 12345;
+        for trial in 0..300 {
+            // Few letters in long inputs give many ties and overlapping pairs like "aaa"
+            let letters = &alphabet[..4 + trial % (alphabet.len() - 3)];
+            let inp: Vec<char> = (0..20 + (trial * 7) % 400).map(|_| {
+                state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                letters[(state >> 33) as usize % letters.len()]
+            }).collect();
+            let rel_lim = if trial % 3 == 0 { 0.02 } else { 0.0 };
+            let reserved = if trial % 2 == 0 { vec!['\u{E000}', 'a'] } else { vec![] };
+
+            let (tokens, grams) = encode_into_ngrams(inp.clone(), rel_lim, 200, &reserved, can_pair);
+            let (expected_tokens, expected_grams) = recounting_encode(inp.clone(), rel_lim, 200, &reserved, can_pair);
+            assert_eq!(tokens, expected_tokens, "tokens of {:?}", inp);
+            assert_eq!(format!("{:?}", grams), format!("{:?}", expected_grams), "grams of {:?}", inp);
+        }
+    }
+}
@@ -0,0 +1,80 @@
+// A compact encoding of the sentence files. Each token is stored as its frequency rank in LEB128: seven bits per
+// byte, least significant first, with the high bit set on every byte but the last. The 128 most common grams take one
+// byte and the next 16256 two, so most tokens take a single byte.
+
+// The ranks-*.bin files give the gram of each rank, so that tokens can be decoded without the gram frequencies.
+
+use std::io::{Write, Result, Error, ErrorKind};
+
+pub fn encoded_len(mut value: usize) -> usize {
+    let mut len = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        len += 1;
+    }
+    len
+}
+
+pub fn encode<W: Write>(out: &mut W, mut value: usize) -> Result<()> {
+    let mut buf = [0u8; 10];
+    let mut len = 0;
+    loop {
+        let byte = (value & 0x7F) as u8;
+        value >>= 7;
+        if value == 0 {
+            buf[len] = byte;
+            len += 1;
+            break;
+        }
+        buf[len] = byte | 0x80;
+        len += 1;
+    }
+    out.write_all(&buf[..len])
+}
+
+// Decodes a whole stream of varints, mapping each rank back to its gram
+pub fn decode(bytes: &[u8], grams_by_rank: &[usize]) -> Result<Vec<usize>> {
+    let mut tokens = Vec::new();
+    let mut value = 0usize;
+    let mut shift = 0;
+
+    for &byte in bytes {
+        if shift >= 64 {
+            return Err(Error::new(ErrorKind::InvalidData, "varint is too long"));
+        }
+        value |= ((byte & 0x7F) as usize) << shift;
+        shift += 7;
+
+        if byte & 0x80 == 0 {
+            let gram = grams_by_rank.get(value).ok_or_else(|| Error::new(ErrorKind::InvalidData, format!("rank {} is out of range", value)))?;
+            tokens.push(*gram);
+            value = 0;
+            shift = 0;
+        }
+    }
+
+    if shift != 0 {
+        return Err(Error::new(ErrorKind::InvalidData, "the last varint is cut off"));
+    }
+    Ok(tokens)
+}
+
+// Returns the rank of each gram, most common first, and the gram of each rank. Ties go to the smaller gram.
+pub fn frequency_ranks<'a, I: IntoIterator<Item=&'a Vec<usize>>>(sentences: I, n_grams: usize) -> (Vec<usize>, Vec<usize>) {
+    let mut counts = vec![0usize; n_grams];
+    for sentence in sentences {
+        for &token in sentence {
+            counts[token] += 1;
+        }
+    }
+
+    let mut grams_by_rank: Vec<usize> = (0..n_grams).collect();
+    grams_by_rank.sort_by_key(|&gram| (std::cmp::Reverse(counts[gram]), gram));
+
+    let mut ranks = vec![0; n_grams];
+    for (rank, &gram) in grams_by_rank.iter().enumerate() {
+        ranks[gram] = rank;
+    }
+
+    (ranks, grams_by_rank)
+}
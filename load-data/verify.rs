@@ -0,0 +1,107 @@
+// Checks that the files of a build agree with each other, beyond what their headers say. Each check stops at the
+// first problem in a file and describes it in the error.
+
+use std::io::{Read, Result, Error, ErrorKind};
+use std::collections::HashSet;
+
+use crate::format::{self, Header, Kind};
+use crate::tokens::{self, Gram};
+use crate::reader::Stream;
+
+fn invalid(path: &str, msg: String) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("{}: {}", path, msg))
+}
+
+fn read_number<R: Read>(inp: &mut R, width: u8) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    inp.read_exact(&mut buf[..width as usize])?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+// Checks that the gram table is in topological order, so that every composition only refers to earlier grams.
+// Returns the number of grams.
+pub fn check_grams(path: &str) -> Result<usize> {
+    let (header, mut reader) = format::open(path, Kind::Grams)?;
+    let grams = tokens::decode_grams(&mut reader, header.count as usize).map_err(|e| invalid(path, e.to_string()))?;
+
+    for (i, gram) in grams.iter().enumerate() {
+        if let &Gram::Composition(a, b) = gram {
+            if a >= i || b >= i {
+                return Err(invalid(path, format!("gram {} is composed of grams {} and {}, which don't come before it", i, a, b)));
+            }
+        }
+    }
+
+    Ok(grams.len())
+}
+
+// Checks that every token is below n_grams, and for varints, that the ranks are a permutation of the grams.
+// Returns the header.
+pub fn check_sentences(path: &str, ranks_path: &str, n_grams: usize) -> Result<Header> {
+    let stream = Stream::open(path, ranks_path)?;
+
+    if let Some(ref grams_by_rank) = stream.grams_by_rank {
+        let mut seen = vec![false; n_grams];
+        for (rank, &gram) in grams_by_rank.iter().enumerate() {
+            if gram >= n_grams || seen[gram] {
+                return Err(invalid(ranks_path, format!("rank {} has gram {}, which is out of range or has another rank", rank, gram)));
+            }
+            seen[gram] = true;
+        }
+    }
+
+    let tokens = stream.all_tokens().map_err(|e| invalid(path, e.to_string()))?;
+    for (i, &token) in tokens.iter().enumerate() {
+        if token >= n_grams {
+            return Err(invalid(path, format!("token {} is {}, but there are only {} grams", i, token, n_grams)));
+        }
+    }
+
+    Ok(stream.file.header.clone())
+}
+
+// Checks that the index of a sentence file starts at 0, never goes back and ends at the end of the sentence file.
+// Returns the start and end of every sentence.
+pub fn check_index(path: &str, sentences: &Header) -> Result<HashSet<(u64, u64)>> {
+    let (header, mut reader) = format::open(path, Kind::Index)?;
+    let size = sentences.count * sentences.width as u64;
+
+    let mut spans = HashSet::new();
+    let mut start = None;
+    for i in 0..header.count {
+        let offset = read_number(&mut reader, header.width)?;
+        match start {
+            None if offset != 0 => return Err(invalid(path, format!("the first sentence starts at {}, not 0", offset))),
+            Some(prev) if offset < prev => return Err(invalid(path, format!("entry {} ({}) is before the entry preceding it ({})", i, offset, prev))),
+            Some(prev) => {
+                spans.insert((prev, offset));
+            }
+            None => {}
+        }
+        start = Some(offset);
+    }
+
+    if start != Some(size) {
+        return Err(invalid(path, format!("the index ends at {:?}, but the sentence file has {} bytes", start, size)));
+    }
+    Ok(spans)
+}
+
+// Checks that both sentences of every link are whole sentences of their sentence files, as given by check_index.
+// Returns the number of links.
+pub fn check_links(path: &str, prim: &HashSet<(u64, u64)>, other: &HashSet<(u64, u64)>) -> Result<u64> {
+    let (header, mut reader) = format::open(path, Kind::Links)?;
+
+    for i in 0..header.count {
+        for (side, spans) in [("primary", prim), ("other", other)].iter() {
+            let offset = read_number(&mut reader, header.width)?;
+            let len = read_number(&mut reader, header.width)?;
+
+            if !offset.checked_add(len).is_some_and(|end| spans.contains(&(offset, end))) {
+                return Err(invalid(path, format!("the {} sentence of link {} ({}+{}) isn't a sentence in the index", side, i, offset, len)));
+            }
+        }
+    }
+
+    Ok(header.count)
+}
@@ -5,7 +5,7 @@ import torch
 import torch.nn as nn
 import torch.nn.functional as F
 import numpy as np
-from sentence_parser import load_one_pair, STYPE_SEC, PRIM_GL, SEC_GL
+from sentence_parser import load_one_pair, load_pair, load_similar_pairs, STYPE_SEC, PRIM_GL, SEC_GL
 
 torch.set_printoptions(precision=5)
 
@@ -146,23 +146,30 @@ def into_one_hot(values, n_tokens):
         one_hot[i, torch.arange(values.size(1)), values[i] % n_tokens] = 1
     return one_hot
 
-def generate_batch(batch_size, other_stype, max_length=None):
+def generate_batch(batch_size, other_stype, max_length=None, split="train", indices=None):
+    # With indices, the batch is those links of the split instead of a random one
     xs, ys = [], []
 
-    if max_length is None:
-        max_length = 3 + random.expovariate(1 / 10)
-        max_length = min(15, max(3, max_length))
-
-    min_length = int(max_length * 0.9 - 2)
-
     longest_x = longest_y = 0
-    for i in range(batch_size):
-        y = None
-        while y is None or not (min_length < len(y) < max_length or min_length < len(x) < max_length):
-            x, y = load_one_pair(other_stype)
-            if max_length == -1:
-                break
 
+    if indices is not None:
+        pairs = [load_pair(other_stype, index, split) for index in indices]
+    elif max_length is None:
+        # Similar lengths are found through the length buckets
+        pairs = load_similar_pairs(other_stype, batch_size, split, max_length=15)
+    else:
+        min_length = int(max_length * 0.9 - 2)
+
+        pairs = []
+        for i in range(batch_size):
+            y = None
+            while y is None or not (min_length < len(y) < max_length or min_length < len(x) < max_length):
+                x, y = load_one_pair(other_stype, split)
+                if max_length == -1:
+                    break
+            pairs.append((x, y))
+
+    for x, y in pairs:
         longest_x = max(longest_x, len(x))
         longest_y = max(longest_y, len(y))
 
@@ -2,6 +2,8 @@ import os
 import random
 from abc import ABC, abstractmethod
 import struct
+import itertools
+from collections import namedtuple
 
 class Gram(ABC):
     @abstractmethod
@@ -144,47 +146,327 @@ class GramList:
         return bpe
 
 
+    def masks_numbers(self):
+        # Builds with NumberMode::Mask reserve the number tokens as their first grams
+        return any(isinstance(gram, Orig) and gram.char == number_token(0) for gram in self.gram_list)
+
     def __str__(self):
         return f"GramList({self.gram_list})"
 
+# Numbers are masked like in load-data/numbers.rs: a number is a run of digits with single '.' or ',' separators,
+# and the n:th distinct number of a sentence becomes number token n. Numbers past the last token are left as they are.
+N_NUMBER_TOKENS = 8
+NUMBER_TOKEN_BASE = 0xE000
+
+def number_token(slot):
+    return chr(NUMBER_TOKEN_BASE + slot)
+
+def mask_numbers(st):
+    # Returns the masked sentence and the masked numbers, in the order of their tokens
+    out = []
+    numbers = []
+    at = 0
+    while at < len(st):
+        if not st[at].isnumeric():
+            out.append(st[at])
+            at += 1
+            continue
+
+        start = at
+        while at < len(st):
+            if st[at].isnumeric():
+                at += 1
+            elif st[at] in ".," and at + 1 < len(st) and st[at + 1].isnumeric():
+                at += 2
+            else:
+                break
+        number = st[start:at]
+
+        if number in numbers:
+            out.append(number_token(numbers.index(number)))
+        elif len(numbers) < N_NUMBER_TOKENS:
+            numbers.append(number)
+            out.append(number_token(len(numbers) - 1))
+        else:
+            out.append(number)
+
+    return "".join(out), numbers
+
+def unmask_numbers(st, numbers):
+    # Puts the numbers masked out of the input back into a translation. Tokens of numbers the input didn't have are
+    # left out.
+    out = []
+    for ch in st:
+        slot = ord(ch) - NUMBER_TOKEN_BASE
+        if 0 <= slot < N_NUMBER_TOKENS:
+            if slot < len(numbers):
+                out.append(numbers[slot])
+        else:
+            out.append(ch)
+    return "".join(out)
+
+def prim_to_bpe(st):
+    # Tokenizes an input sentence like the build did. Returns the tokens and the numbers to unmask the output with.
+    numbers = []
+    if PRIM_GL.masks_numbers():
+        st, numbers = mask_numbers(st)
+    return PRIM_GL.str_to_bpe(st), numbers
+
 STYPE_PRIM = 0
 STYPE_SEC = 1
 STYPE_AUX = 2
 
-def open_size(path):
-    return open(os.path.expanduser(path), "rb"), os.path.getsize(os.path.expanduser(path))
+# Every file written by select-langs starts with a header, see load-data/format.rs
+HEADER_SIZE = 0x28
+FORMAT_VERSION = 1
+
+KIND_GRAMS = 1
+KIND_SENTENCES = 2
+KIND_LINKS = 3
+KIND_BUCKETS = 4
+KIND_PERMUTATIONS = 5
+KIND_WEIGHTS = 6
+KIND_FOLDS = 7
+KIND_IDS = 8
+KIND_CONTAINER = 9
+KIND_VARINTS = 10
+KIND_RANKS = 11
+KIND_INDEX = 12
+
+Header = namedtuple("Header", ["kind", "width", "language", "count", "fingerprint"])
+
+# All files have to come from the same build
+build_fingerprint = None
+
+def open_artifact(path, kind):
+    # Returns the opened file positioned after its header, and the header. kind can also be a tuple of kinds.
+    global build_fingerprint
+
+    f = open(os.path.expanduser(path), "rb")
+    magic, version, file_kind, width, language, count, fingerprint = struct.unpack("<4sHBB16sQQ", f.read(HEADER_SIZE))
+
+    if magic != b"ILPT":
+        raise Exception(f"{path} is not a select-langs file")
+    if version != FORMAT_VERSION:
+        raise Exception(f"{path} has format version {version}, expected {FORMAT_VERSION}")
+    if file_kind not in (kind if isinstance(kind, tuple) else (kind,)):
+        raise Exception(f"{path} has kind {file_kind}, expected {kind}")
+
+    if build_fingerprint is None:
+        build_fingerprint = fingerprint
+    elif fingerprint != build_fingerprint:
+        raise Exception(f"{path} is from a different build ({fingerprint:016x}, expected {build_fingerprint:016x})")
+
+    return f, Header(file_kind, width, language.rstrip(b"\0").decode(), count, fingerprint)
+
+SPLITS = ["train", "dev", "test"]
+
+# split -> (file, header)
+sec_links = {split: open_artifact(f"cache/sec-links-{split}.bin", KIND_LINKS) for split in SPLITS}
+aux_links = {split: open_artifact(f"cache/aux-links-{split}.bin", KIND_LINKS) for split in SPLITS}
+
+def read_buckets(path):
+    # Each bucket is (min length, max length, first link, number of links)
+    f, _ = open_artifact(path, KIND_BUCKETS)
+    with f:
+        return [bucket for bucket in struct.iter_unpack("<4I", f.read()) if bucket[3] > 0]
+
+sec_buckets = {split: read_buckets(f"cache/sec-buckets-{split}.bin") for split in SPLITS}
+aux_buckets = {split: read_buckets(f"cache/aux-buckets-{split}.bin") for split in SPLITS}
+
+def read_cum_weights(path):
+    f, _ = open_artifact(path, KIND_WEIGHTS)
+    with f:
+        return list(itertools.accumulate(weight for weight, in struct.iter_unpack("<f", f.read())))
+
+# Sampling weights of the train links, summing to 1 over both tasks
+sec_cum_weights = read_cum_weights("cache/sec-weights.bin")
+aux_cum_weights = read_cum_weights("cache/aux-weights.bin")
+
+# How often to train on each task
+TASK_WEIGHTS = (sec_cum_weights[-1], aux_cum_weights[-1])
+
+# Sentence files -> the gram of each rank, for sentence files written with VARINT_SENTENCES
+sents_ranks = {}
+
+def open_sentences(name):
+    f, header = open_artifact(f"cache/sentences-{name}.bin", (KIND_SENTENCES, KIND_VARINTS))
+    if header.kind == KIND_VARINTS:
+        ranks_file, ranks_header = open_artifact(f"cache/ranks-{name}.bin", KIND_RANKS)
+        with ranks_file:
+            sents_ranks[f] = struct.unpack(f"<{ranks_header.count}I", ranks_file.read())
+    return f, header
+
+sents_prim, sents_header = open_sentences("prim")
+sents_sec, _ = open_sentences("sec")
+sents_aux, _ = open_sentences("aux")
+
+# Tokens are 16-bit, or 32-bit for large vocabularies. All sentence files of a build use the same width.
+TOKEN_WIDTH = sents_header.width
+TOKEN_FORMAT = "H" if TOKEN_WIDTH == 2 else "I"
+
+def read_tokens(sents, start, length):
+    # Offsets are counted from the end of the sentence file's header
+    sents.seek(HEADER_SIZE + start)
+    data = sents.read(length)
+
+    grams_by_rank = sents_ranks.get(sents)
+    if grams_by_rank is None:
+        return list(struct.unpack(f"<{length // TOKEN_WIDTH}{TOKEN_FORMAT}", data))
+
+    # Varints of the ranks, seven bits per byte, see load-data/varint.rs
+    tokens = []
+    value, shift = 0, 0
+    for byte in data:
+        value |= (byte & 0x7F) << shift
+        shift += 7
+        if byte < 0x80:
+            tokens.append(grams_by_rank[value])
+            value, shift = 0, 0
+    return tokens
+
+def load_one_pair(other_stype, split="train"):
+    _, links_header = (sec_links if other_stype == STYPE_SEC else aux_links)[split]
+
+    n_links = links_header.count
+    if split == "train":
+        cum_weights = sec_cum_weights if other_stype == STYPE_SEC else aux_cum_weights
+        selected, = random.choices(range(n_links), cum_weights=cum_weights)
+    else:
+        selected = random.randrange(0, n_links)
+
+    return load_pair(other_stype, selected, split)
+
+def load_pair(other_stype, index, split="train"):
+    links_file, links_header = (sec_links if other_stype == STYPE_SEC else aux_links)[split]
+    sents_other = sents_sec if other_stype == STYPE_SEC else sents_aux
+    link_size = 4 * links_header.width
+
+    links_file.seek(HEADER_SIZE + index * link_size)
 
-sec_links, sec_links_size = open_size("cache/sec-links.bin")
-aux_links, aux_links_size = open_size("cache/aux-links.bin")
+    return load_link(links_file.read(link_size), sents_other)
 
-sents_prim = open(os.path.expanduser("cache/sentences-prim.bin"), "rb")
-sents_sec = open(os.path.expanduser("cache/sentences-sec.bin"), "rb")
-sents_aux = open(os.path.expanduser("cache/sentences-aux.bin"), "rb")
+def load_curriculum_pair(other_stype, fraction):
+    # Loads a random pair among the easiest fraction of the train links
+    name = "sec" if other_stype == STYPE_SEC else "aux"
+    sents_other = sents_sec if other_stype == STYPE_SEC else sents_aux
 
-def load_one_pair(other_stype):
-    links_file = sec_links if other_stype == STYPE_SEC else aux_links
-    links_size = sec_links_size if other_stype == STYPE_SEC else aux_links_size
+    f, header = open_artifact(f"cache/{name}-curriculum.bin", KIND_LINKS)
+    selected = random.randrange(0, max(1, int(header.count * fraction)))
+    link_size = 4 * header.width
+
+    with f:
+        f.seek(HEADER_SIZE + selected * link_size)
+        return load_link(f.read(link_size), sents_other)
+
+def fold_indices(fold):
+    # The indices of the secondary train links to train on and to evaluate on for a cross-validation fold.
+    # Only available if select-langs was built with N_FOLDS.
+    f, _ = open_artifact("cache/sec-folds.bin", KIND_FOLDS)
+    with f:
+        folds = f.read()
+
+    train = [i for i, link_fold in enumerate(folds) if link_fold != fold]
+    held_out = [i for i, link_fold in enumerate(folds) if link_fold == fold]
+    return train, held_out
+
+def epoch_order(other_stype, epoch):
+    # The order in which to visit the train links during an epoch, so each pair is seen exactly once.
+    # Epochs past the ones written by select-langs reuse earlier permutations, run `select-langs permute` for more.
+    name = "sec" if other_stype == STYPE_SEC else "aux"
+    _, links_header = (sec_links if other_stype == STYPE_SEC else aux_links)["train"]
+    n_links = links_header.count
+    if n_links == 0:
+        return []
+
+    f, header = open_artifact(f"cache/{name}-epochs.bin", KIND_PERMUTATIONS)
+    with f:
+        permutations = f.read()
+    n_epochs = header.count // n_links
+    if n_epochs == 0:
+        raise ValueError(f"cache/{name}-epochs.bin has no permutations, run `select-langs permute`")
+
+    return list(struct.unpack_from(f"<{n_links}I", permutations, (epoch % n_epochs) * 4 * n_links))
+
+def load_similar_pairs(other_stype, n, split="train", max_length=None):
+    # Loads n pairs of similar length from a random length bucket, reading all its links at once.
+    # In train, the bucket and the links within it are sampled by the link weights, like in load_one_pair.
+    links_file, links_header = (sec_links if other_stype == STYPE_SEC else aux_links)[split]
+    link_size = 4 * links_header.width
+    buckets = (sec_buckets if other_stype == STYPE_SEC else aux_buckets)[split]
+    if max_length is not None:
+        buckets = [bucket for bucket in buckets if bucket[1] < max_length] or buckets[:1]
     sents_other = sents_sec if other_stype == STYPE_SEC else sents_aux
 
-    n_links = links_size // (4 * 4)
-    selected = random.randrange(0, n_links)
-    file_offset = selected * 4 * 4
+    if split == "train":
+        cum_weights = sec_cum_weights if other_stype == STYPE_SEC else aux_cum_weights
+        # The total weight of the links before index i
+        weight_before = lambda i: cum_weights[i - 1] if i > 0 else 0.0
+        bucket_weights = [weight_before(start + count) - weight_before(start) for _, _, start, count in buckets]
+    else:
+        bucket_weights = [bucket[3] for bucket in buckets]
+
+    _, _, start, count = random.choices(buckets, weights=bucket_weights)[0]
+
+    links_file.seek(HEADER_SIZE + start * link_size)
+    links = links_file.read(count * link_size)
 
-    links_file.seek(file_offset)
+    if split == "train":
+        base = weight_before(start)
+        selected = random.choices(range(count), cum_weights=[w - base for w in cum_weights[start:start + count]], k=n)
+    else:
+        n_read = min(n, count)
+        first = random.randrange(0, count - n_read + 1)
+        selected = list(range(first, first + n_read))
+        # Small buckets are repeated to fill the batch
+        selected += [random.choice(selected) for _ in range(n - n_read)]
 
-    p_start, p_len, o_start, o_len = struct.unpack("<4I", links_file.read(4 * 4))
+    return [load_link(links[i * link_size:(i + 1) * link_size], sents_other) for i in selected]
 
-    sents_prim.seek(p_start)
-    prim_sent = list(struct.unpack(f"<{p_len // 2}H", sents_prim.read(p_len)))
+def load_link(link, sents_other):
+    # Offsets and lengths are in bytes. They are 64-bit for very large sentence files.
+    p_start, p_len, o_start, o_len = struct.unpack("<4I" if len(link) == 4 * 4 else "<4Q", link)
 
-    sents_other.seek(o_start)
-    other_sent = list(struct.unpack(f"<{o_len // 2}H", sents_other.read(o_len)))
+    prim_sent = read_tokens(sents_prim, p_start, p_len)
+    other_sent = read_tokens(sents_other, o_start, o_len)
 
     return prim_sent + [-1], other_sent + [-1]
 
-PRIM_GL = GramList.from_file(open(os.path.expanduser("cache/ngrams-prim.bin"), "rb"))
-SEC_GL = GramList.from_file(open(os.path.expanduser("cache/ngrams-sec.bin"), "rb"))
-AUX_GL = GramList.from_file(open(os.path.expanduser("cache/ngrams-aux.bin"), "rb"))
+# The NumPy arrays written with WRITE_NPY, memory-mapped so that only the parts which are used get read
+npy_arrays = {}
+
+def npy_array(name):
+    import numpy as np
+
+    if name not in npy_arrays:
+        npy_arrays[name] = np.load(f"cache/npy/{name}.npy", mmap_mode="r")
+    return npy_arrays[name]
+
+def npy_gather(tokens, starts, ends):
+    # Rows of tokens[start:end], padded with -1, which also marks the end of each sentence like in load_link
+    import numpy as np
+
+    lengths = (ends - starts).astype(np.int64)
+    positions = np.arange(lengths.max() + 1)
+    indices = starts[:, None].astype(np.int64) + positions
+    in_sentence = positions < lengths[:, None]
+    return np.where(in_sentence, tokens[np.minimum(indices, len(tokens) - 1)], -1)
+
+def load_npy_batch(other_stype, indices, split="train"):
+    # Loads the pairs at the given link indices as two matrices of tokens, without unpacking sentences one by one
+    name = "sec" if other_stype == STYPE_SEC else "aux"
+    links = npy_array(f"{name}-links-{split}")[indices]
+
+    prim_offsets = npy_array("offsets-prim")
+    other_offsets = npy_array(f"offsets-{name}")
+
+    prim = npy_gather(npy_array("tokens-prim"), prim_offsets[links[:, 0]], prim_offsets[links[:, 0] + 1])
+    other = npy_gather(npy_array(f"tokens-{name}"), other_offsets[links[:, 1]], other_offsets[links[:, 1] + 1])
+    return prim, other
+
+PRIM_GL = GramList.from_file(open_artifact("cache/ngrams-prim.bin", KIND_GRAMS)[0])
+SEC_GL = GramList.from_file(open_artifact("cache/ngrams-sec.bin", KIND_GRAMS)[0])
+AUX_GL = GramList.from_file(open_artifact("cache/ngrams-aux.bin", KIND_GRAMS)[0])
 
 if __name__ == "__main__":
     prim, sec = load_one_pair(STYPE_SEC)
@@ -5,7 +5,7 @@ import torch
 import torch.nn as nn
 import torch.nn.functional as F
 
-from sentence_parser import STYPE_SEC, STYPE_AUX, PRIM_GL, SEC_GL, AUX_GL
+from sentence_parser import STYPE_SEC, STYPE_AUX, PRIM_GL, SEC_GL, AUX_GL, TASK_WEIGHTS, epoch_order
 from network import device, Encoder, Decoder, into_one_hot, generate_batch, load_from_save, save
 
 if device.type == "cuda":
@@ -15,6 +15,25 @@ else:
 
 print(f"Using batch size of {BATCH_SIZE}")
 
+# With EPOCH_ORDER, each task goes through its train links in the permutations written by select-langs, so that every
+# pair is seen once per pass, instead of sampling batches of similar lengths
+EPOCH_ORDER = False
+
+# Task -> [number of passes started, indices of the links left in the current pass]
+passes = {STYPE_SEC: [0, []], STYPE_AUX: [0, []]}
+
+def next_indices(stype):
+    # The next batch of the current pass, or None to sample a batch when the task has no train links
+    state = passes[stype]
+    if not state[1]:
+        state[1] = epoch_order(stype, state[0])
+        state[0] += 1
+    if not state[1]:
+        return None
+
+    indices, state[1] = state[1][:BATCH_SIZE], state[1][BATCH_SIZE:]
+    return indices
+
 def display_tokens(toklist, gl):
     out = ""
     last = 0 # 0 = any char, 1 = end, 2 = end after another end
@@ -52,9 +71,9 @@ if __name__ == "__main__":
 
         for batch_nr in range(16):
             print(hex(batch_nr)[2:], end=" ")
-            for name, losses, dec, opt, stype in [sec_info, aux_info, aux_info]:
+            for name, losses, dec, opt, stype in random.choices([sec_info, aux_info], weights=TASK_WEIGHTS, k=3):
                 print(name, end=":")
-                xs, ys = generate_batch(BATCH_SIZE, stype)
+                xs, ys = generate_batch(BATCH_SIZE, stype, indices=next_indices(stype) if EPOCH_ORDER else None)
 
                 print("l={:2d}/{:2d};".format(xs.size(1), ys.size(1)), end=" ", flush=True)
 
@@ -94,7 +113,7 @@ if __name__ == "__main__":
 
         for name, losses, dec, opt, stype in [sec_info, aux_info]:
             print(f"For {name}")
-            xs, ys = generate_batch(4, stype, max_length=10)
+            xs, ys = generate_batch(4, stype, max_length=10, split="dev")
             gl = SEC_GL if stype == STYPE_SEC else AUX_GL
 
             hids = enc(xs)